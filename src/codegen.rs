@@ -0,0 +1,284 @@
+//! Generation of Rust code for a machine described by a `MachineDescription`,
+//! meant to be called from `build.rs`.
+//!
+//! The generated code contains the state and event enums with their name tables
+//! and a constructor adding all transitions. Transitions are bound to functions
+//! named after the snake cased transition names in the handler module, so a
+//! missing or mistyped handler is a compile error instead of a load error.
+//! Transitions described with several targets become dynamic transitions whose
+//! handler returns the end state, guards of choices are bound to handler
+//! predicates the same way. Completion transitions are bound like entry/exit
+//! transitions, histories need no handler.
+//!
+//! ```ignore
+//! // build.rs
+//! let desc = MachineDescription::from_scxml(&fs::read_to_string("coin.scxml")?)?;
+//! let out = Path::new(&env::var("OUT_DIR")?).join("coin.rs");
+//! codegen::generate_file(&desc, &CodegenOptions::new("Still", "Args", "Errs"), &out)?;
+//!
+//! // lib.rs
+//! include!(concat!(env!("OUT_DIR"), "/coin.rs"));
+//! ```
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use load::MachineDescription;
+use super::{EntryExit, TransitionKind, HistoryKind};
+
+/// what to generate and which types the generated machine uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodegenOptions {
+	/// name of the generated state enum
+	pub state_enum: String,
+	/// name of the generated event enum
+	pub event_enum: String,
+	/// type of the extended state
+	pub extended_state: String,
+	/// type of the transition arguments
+	pub arguments: String,
+	/// type of the errors transitions generate
+	pub error: String,
+	/// path of the module the handler functions live in
+	pub handlers: String,
+	/// name of the generated constructor
+	pub constructor: String,
+}
+
+impl CodegenOptions {
+	/// options with enums named `States` and `Events`, handlers in `handlers` and
+	/// constructor `build_fsm`
+	///
+	///   * `extended_state`, `arguments`, `error` - type paths used by the machine
+	pub fn new(extended_state: &str, arguments: &str, error: &str) -> CodegenOptions {
+		CodegenOptions {
+			state_enum: String::from("States"),
+			event_enum: String::from("Events"),
+			extended_state: String::from(extended_state),
+			arguments: String::from(arguments),
+			error: String::from(error),
+			handlers: String::from("handlers"),
+			constructor: String::from("build_fsm"),
+		}
+	}
+}
+
+/// `ProcessCoin` -> `process_coin`
+pub fn snake_case(name: &str) -> String {
+	let mut out = String::new();
+	let mut prev_lower = false;
+	for c in name.chars() {
+		if c.is_uppercase() {
+			if prev_lower {
+				out.push('_');
+			}
+			out.extend(c.to_lowercase());
+			prev_lower = false;
+		} else if c.is_alphanumeric() {
+			out.push(c);
+			prev_lower = true;
+		} else {
+			if !out.is_empty() && !out.ends_with('_') {
+				out.push('_');
+			}
+			prev_lower = false;
+		}
+	}
+	out
+}
+
+fn is_identifier(name: &str) -> bool {
+	let mut chars = name.chars();
+	match chars.next() {
+		Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+			chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+		}
+		_ => false,
+	}
+}
+
+fn invalid(why: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidInput, why)
+}
+
+/// writes an enum together with a name table usable for `dotfile` and the exports
+fn write_enum<W: io::Write>(w: &mut W, name: &str, variants: &BTreeSet<&str>)
+                            -> Result<(), io::Error> {
+	writeln!(w, "#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]")?;
+	writeln!(w, "pub enum {} {{", name)?;
+	for v in variants.iter() {
+		writeln!(w, "\t{},", v)?;
+	}
+	writeln!(w, "}}")?;
+	writeln!(w)?;
+	writeln!(w, "impl {} {{", name)?;
+	writeln!(w, "\t/// all variants with their names")?;
+	writeln!(w, "\tpub fn names() -> ::std::collections::HashMap<{}, &'static str> {{", name)?;
+	writeln!(w, "\t\tlet mut names = ::std::collections::HashMap::new();")?;
+	for v in variants.iter() {
+		writeln!(w, "\t\tnames.insert({}::{}, \"{}\");", name, v, v)?;
+	}
+	writeln!(w, "\t\tnames")?;
+	writeln!(w, "\t}}")?;
+	writeln!(w, "}}")?;
+	writeln!(w)
+}
+
+/// generates the enums and the constructor for the machine in `desc`. States and
+/// events have to be valid Rust identifiers, transitions need a name to derive
+/// their handler function from.
+pub fn generate<W: io::Write>(desc: &MachineDescription,
+                              options: &CodegenOptions,
+                              w: &mut W)
+                              -> Result<(), io::Error> {
+	let mut states = desc.states.iter().map(|s| s.as_str()).collect::<BTreeSet<_>>();
+	states.insert(&desc.start);
+	let mut events = desc.events.iter().map(|e| e.as_str()).collect::<BTreeSet<_>>();
+	for t in desc.transitions.iter() {
+		states.insert(&t.from);
+		states.insert(&t.to);
+		events.insert(&t.event);
+	}
+	for ee in desc.entryexit.iter() {
+		states.insert(&ee.state);
+	}
+	for b in desc.choices.iter() {
+		states.insert(&b.choice);
+		states.insert(&b.to);
+	}
+	for h in desc.histories.iter() {
+		states.insert(&h.history);
+		states.extend(h.members.iter().map(|m| m.as_str()));
+	}
+	for c in desc.completions.iter() {
+		states.insert(&c.state);
+		states.insert(&c.to);
+	}
+
+	for n in states.iter().chain(events.iter()) {
+		if !is_identifier(n) {
+			return Err(invalid(format!("`{}` is not a valid identifier", n)));
+		}
+	}
+	for n in desc.transitions.iter().map(|t| &t.name)
+		.chain(desc.entryexit.iter().map(|ee| &ee.name))
+		.chain(desc.choices.iter().filter_map(|b| b.guard.as_ref()))
+		.chain(desc.completions.iter().map(|c| &c.name))
+		.chain(desc.completions.iter().filter_map(|c| c.guard.as_ref())) {
+		if !is_identifier(&snake_case(n)) {
+			return Err(invalid(format!("no handler function can be named after `{}`", n)));
+		}
+	}
+	let choices = desc.choice_groups();
+	for group in choices.iter() {
+		if group.iter().filter(|b| b.guard.is_none()).count() != 1 {
+			return Err(invalid(format!("choice `{}` needs exactly one branch taken if no guard holds",
+			                           group[0].choice)));
+		}
+	}
+	for h in desc.histories.iter() {
+		if !h.members.contains(&h.initial) {
+			return Err(invalid(format!("history `{}` starts in `{}` which is no member",
+			                           h.history, h.initial)));
+		}
+	}
+
+	let fsm_type = format!("::extfsm::FSM<{}, {}, {}, {}, {}>",
+	                       options.extended_state, options.state_enum, options.event_enum,
+	                       options.arguments, options.error);
+
+	writeln!(w, "// generated by extfsm::codegen from the description of `{}`, do not edit",
+	         desc.name)?;
+	writeln!(w)?;
+	write_enum(w, &options.state_enum, &states)?;
+	write_enum(w, &options.event_enum, &events)?;
+
+	writeln!(w, "/// builds the `{}` machine with the transitions from `{}`",
+	         desc.name, options.handlers)?;
+	writeln!(w, "pub fn {}(extended_init: Box<{}>, log: ::slog::Logger) -> {} {{",
+	         options.constructor, options.extended_state, fsm_type)?;
+	writeln!(w, "\tlet mut fsm: {} = ::extfsm::FSM::new({}::{}, extended_init, \"{}\", log);",
+	         fsm_type, options.state_enum, desc.start, desc.name.escape_default())?;
+	for group in desc.transition_groups() {
+		let t = group[0];
+		let kind = if group.iter().any(|d| d.kind == TransitionKind::External) {
+			".with_kind(::extfsm::TransitionKind::External)"
+		} else {
+			""
+		};
+		writeln!(w, "\tfsm.add_transition(::extfsm::TransitionSource::new({}::{}, {}::{}),",
+		         options.state_enum, t.from, options.event_enum, t.event)?;
+		if group.len() > 1 {
+			let targets = group.iter()
+				.map(|d| format!("{}::{}", options.state_enum, d.to))
+				.collect::<Vec<_>>();
+			writeln!(w, "\t                   ::extfsm::TransitionTarget::dynamic({}, vec![{}], \
+			             Box::new({}::{}), Some(\"{}\")){});",
+			         targets[0], targets[1..].join(", "), options.handlers, snake_case(&t.name),
+			         t.name.escape_default(), kind)?;
+		} else {
+			writeln!(w, "\t                   ::extfsm::TransitionTarget::new({}::{}, Box::new({}::{}), \
+			             Some(\"{}\")){});",
+			         options.state_enum, t.to, options.handlers, snake_case(&t.name),
+			         t.name.escape_default(), kind)?;
+		}
+	}
+	for ee in desc.entryexit.iter() {
+		let kind = match ee.kind {
+			EntryExit::EntryTransition => "EntryTransition",
+			EntryExit::ExitTransition => "ExitTransition",
+		};
+		writeln!(w, "\tfsm.add_enter_transition(({}::{}, ::extfsm::EntryExit::{}),",
+		         options.state_enum, ee.state, kind)?;
+		writeln!(w, "\t                         Box::new({}::{}), Some(\"{}\"));",
+		         options.handlers, snake_case(&ee.name), ee.name.escape_default())?;
+	}
+	for group in choices.iter() {
+		let branches = group.iter().filter_map(|b| {
+			b.guard.as_ref().map(|g| {
+				format!("::extfsm::ChoiceBranch::new({}::{}, Box::new({}::{}), Some(\"{}\"))",
+				        options.state_enum, b.to, options.handlers, snake_case(g), g.escape_default())
+			})
+		}).collect::<Vec<_>>();
+		let otherwise = group.iter().find(|b| b.guard.is_none()).map_or("", |b| b.to.as_str());
+		writeln!(w, "\tfsm.add_choice({}::{},", options.state_enum, group[0].choice)?;
+		writeln!(w, "\t               vec![{}],", branches.join(",\n\t                    "))?;
+		writeln!(w, "\t               {}::{});", options.state_enum, otherwise)?;
+	}
+	for h in desc.histories.iter() {
+		let kind = match h.kind {
+			HistoryKind::Shallow => "Shallow",
+			HistoryKind::Deep => "Deep",
+		};
+		let members = h.members.iter()
+			.map(|m| format!("{}::{}", options.state_enum, m))
+			.collect::<Vec<_>>();
+		writeln!(w, "\tfsm.add_history({}::{}, ::extfsm::HistoryKind::{}, vec![{}], {}::{});",
+		         options.state_enum, h.history, kind, members.join(", "), options.state_enum, h.initial)?;
+	}
+	for c in desc.completions.iter() {
+		let guard = c.guard.as_ref().map_or(String::new(), |g| {
+			format!(".with_guard(Box::new({}::{}), Some(\"{}\"))",
+			        options.handlers, snake_case(g), g.escape_default())
+		});
+		writeln!(w, "\tfsm.add_completion({}::{},", options.state_enum, c.state)?;
+		writeln!(w, "\t                   ::extfsm::CompletionTransition::new({}::{}, Box::new({}::{}), \
+		             Some(\"{}\")){});",
+		         options.state_enum, c.to, options.handlers, snake_case(&c.name), c.name.escape_default(),
+		         guard)?;
+	}
+	writeln!(w, "\tfsm")?;
+	writeln!(w, "}}")
+}
+
+/// generates the code for `desc` into the file `out`, typically within `OUT_DIR`
+pub fn generate_file<P: AsRef<Path>>(desc: &MachineDescription,
+                                     options: &CodegenOptions,
+                                     out: P)
+                                     -> Result<(), io::Error> {
+	let mut code = Vec::new();
+	generate(desc, options, &mut code)?;
+	fs::write(out, code)
+}
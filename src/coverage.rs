@@ -0,0 +1,283 @@
+//! Transition coverage of test suites.
+//!
+//! Every machine counts the transitions and entry/exit transitions it executed.
+//! `FSM::coverage` takes these counts for all transitions the machine declares,
+//! coverages of the many machines a test suite builds are merged and the result
+//! is reported as text or as dot graph with untested transitions highlighted.
+//! `Coverage::assert_complete` fails a test, and so CI, if a declared transition
+//! has never been executed.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+
+use super::{FSM, DotOptions, DotEdgeKey, EntryExit, EntryExitKey, ChoiceKey, TransitionEdge,
+            TransitionSource};
+use export::render_string;
+
+/// color of transitions never executed in the coverage dot graph
+const DOT_UNTESTED_COLOR: &str = "red";
+
+/// a declared transition and how often it has been executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Counted<StateType> {
+	endstate: StateType,
+	name: Option<String>,
+	count: u64,
+}
+
+/// execution counts of all declared transitions of one or more machines
+#[derive(Clone)]
+pub struct Coverage<StateType, EventType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	/// dynamic transitions are counted per target
+	transitions: HashMap<TransitionEdge<StateType, EventType>, Counted<StateType>>,
+	entryexit: HashMap<EntryExitKey<StateType>, Counted<StateType>>,
+	/// branches of choice pseudo-states
+	choices: HashMap<ChoiceKey<StateType>, Counted<StateType>>,
+	/// completion transitions per state
+	completions: HashMap<StateType, Counted<StateType>>,
+}
+
+impl<StateType, EventType> Default for Coverage<StateType, EventType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	fn default() -> Coverage<StateType, EventType> {
+		Coverage {
+			transitions: HashMap::new(),
+			entryexit: HashMap::new(),
+			choices: HashMap::new(),
+			completions: HashMap::new(),
+		}
+	}
+}
+
+impl<StateType, EventType> Coverage<StateType, EventType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	/// adds the counts of `other`, transitions declared in either are covered
+	pub fn merge(&mut self, other: &Coverage<StateType, EventType>) {
+		for (k, c) in other.transitions.iter() {
+			self.transitions.entry(k.clone())
+				.or_insert_with(|| Counted { count: 0, ..c.clone() })
+				.count += c.count;
+		}
+		for (k, c) in other.entryexit.iter() {
+			self.entryexit.entry(k.clone())
+				.or_insert_with(|| Counted { count: 0, ..c.clone() })
+				.count += c.count;
+		}
+		for (k, c) in other.choices.iter() {
+			self.choices.entry(k.clone())
+				.or_insert_with(|| Counted { count: 0, ..c.clone() })
+				.count += c.count;
+		}
+		for (k, c) in other.completions.iter() {
+			self.completions.entry(k.clone())
+				.or_insert_with(|| Counted { count: 0, ..c.clone() })
+				.count += c.count;
+		}
+	}
+
+	/// `returns` - number of transitions executed at least once and number of all
+	/// transitions, both including entry/exit transitions, choice branches and
+	/// completion transitions
+	pub fn covered(&self) -> (usize, usize) {
+		let counts = self.transitions.values()
+			.chain(self.entryexit.values())
+			.chain(self.choices.values())
+			.chain(self.completions.values());
+		let total = self.transitions.len() + self.entryexit.len() + self.choices.len() +
+			self.completions.len();
+		(counts.filter(|c| c.count > 0).count(), total)
+	}
+
+	/// `returns` - TRUE if every transition has been executed
+	pub fn is_complete(&self) -> bool {
+		let (covered, total) = self.covered();
+		covered == total
+	}
+
+	/// how often the transition on `event` in `state` has been executed to any of
+	/// its targets, None if it is not declared
+	pub fn transition_count(&self, state: StateType, event: EventType) -> Option<u64> {
+		let source = TransitionSource::new(state, event);
+		self.transitions.iter()
+			.filter(|&(k, _)| k.0 == source)
+			.map(|(_, c)| c.count)
+			.fold(None, |sum, c| Some(sum.unwrap_or(0) + c))
+	}
+
+	/// how often the entry or exit transition of `state` has been executed, None
+	/// if it is not declared
+	pub fn entryexit_count(&self, state: StateType, kind: EntryExit) -> Option<u64> {
+		self.entryexit.get(&(state, kind)).map(|c| c.count)
+	}
+
+	fn count(&self, k: &DotEdgeKey<StateType, EventType>) -> u64 {
+		match *k {
+			DotEdgeKey::Transition(ref t) => self.transitions.get(t),
+			DotEdgeKey::EntryExit(ref eek) => self.entryexit.get(eek),
+			DotEdgeKey::Choice(ref ck) => self.choices.get(ck),
+			DotEdgeKey::Completion(ref s) => self.completions.get(s),
+		}.map(|c| c.count).unwrap_or(0)
+	}
+
+	/// one line per transition with its count, sorted
+	fn lines(&self,
+	         state2name: &HashMap<StateType, &'static str>,
+	         event2name: &HashMap<EventType, &'static str>)
+	         -> Vec<(String, u64)> {
+		let sn = |s: &StateType| *state2name.get(s).unwrap_or(&"?");
+		let mut lines = self.transitions.iter().map(|(k, c)| {
+			(format!("{} |{}| -> {} {}", sn(&k.0.state), event2name.get(&k.0.event).unwrap_or(&"?"),
+			         sn(&c.endstate), c.name.as_deref().unwrap_or("")),
+			 c.count)
+		}).chain(self.entryexit.iter().map(|(k, c)| {
+			let kind = match k.1 {
+				EntryExit::EntryTransition => "Enter",
+				EntryExit::ExitTransition => "Exit",
+			};
+			(format!("{} {} {}", kind, sn(&k.0), c.name.as_deref().unwrap_or("")), c.count)
+		})).chain(self.choices.iter().map(|(k, c)| {
+			(format!("Choice {} -> {} {}", sn(&k.0), sn(&k.1), c.name.as_deref().unwrap_or("")),
+			 c.count)
+		})).chain(self.completions.iter().map(|(k, c)| {
+			(format!("Complete {} -> {} {}", sn(k), sn(&c.endstate), c.name.as_deref().unwrap_or("")),
+			 c.count)
+		})).map(|(l, c)| (String::from(l.trim_end()), c)).collect::<Vec<_>>();
+		lines.sort();
+		lines
+	}
+
+	/// descriptions of all transitions never executed, sorted
+	pub fn untested(&self,
+	                state2name: &HashMap<StateType, &'static str>,
+	                event2name: &HashMap<EventType, &'static str>)
+	                -> Vec<String> {
+		self.lines(state2name, event2name).into_iter()
+			.filter(|&(_, c)| c == 0)
+			.map(|(l, _)| l)
+			.collect()
+	}
+
+	/// writes a report listing all transitions with their counts
+	pub fn render_report<W: io::Write>(&self, w: &mut W,
+	                                   state2name: &HashMap<StateType, &'static str>,
+	                                   event2name: &HashMap<EventType, &'static str>,
+	) -> Result<(), io::Error> {
+		let (covered, total) = self.covered();
+		writeln!(w, "{} of {} transitions covered", covered, total)?;
+		for (l, c) in self.lines(state2name, event2name) {
+			if c == 0 {
+				writeln!(w, "  UNTESTED {}", l)?;
+			} else {
+				writeln!(w, "  {:>7}x {}", c, l)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// provides the report of `render_report` as a string
+	pub fn report_string(&self,
+	                     state2name: &HashMap<StateType, &'static str>,
+	                     event2name: &HashMap<EventType, &'static str>,
+	) -> Result<String, io::Error> {
+		render_string(|out| self.render_report(out, state2name, event2name))
+	}
+
+	/// # Panics
+	/// with the report if a transition has never been executed
+	pub fn assert_complete(&self,
+	                       state2name: &HashMap<StateType, &'static str>,
+	                       event2name: &HashMap<EventType, &'static str>) {
+		if !self.is_complete() {
+			panic!("incomplete transition coverage, {}",
+			       self.report_string(state2name, event2name)
+				       .unwrap_or_else(|e| e.to_string()));
+		}
+	}
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	/// `returns` - all declared transitions with how often this machine executed them
+	pub fn coverage(&self) -> Coverage<StateType, EventType> {
+		let mut c = Coverage::default();
+		for (k, t) in self.transitions.iter() {
+			for target in t.targets.iter() {
+				let edge = (k.clone(), target.clone());
+				let count = self.stats.transitions.get(&edge).cloned().unwrap_or(0);
+				c.transitions.insert(edge, Counted {
+					endstate: target.clone(),
+					name: t.name.clone(),
+					count,
+				});
+			}
+		}
+		for (k, choice) in self.choices.iter() {
+			for (target, name) in choice.branches() {
+				let key = (k.clone(), target.clone());
+				c.choices.insert(key.clone(), Counted {
+					endstate: target.clone(),
+					name: Some(format!("[{}]", name.unwrap_or("else"))),
+					count: self.stats.choices.get(&key).cloned().unwrap_or(0),
+				});
+			}
+		}
+		for (k, t) in self.statetransitions.iter() {
+			c.entryexit.insert(k.clone(), Counted {
+				endstate: k.0.clone(),
+				name: t.1.clone(),
+				count: self.stats.entryexit.get(k).cloned().unwrap_or(0),
+			});
+		}
+		for (k, t) in self.completions.iter() {
+			c.completions.insert(k.clone(), Counted {
+				endstate: t.endstate.clone(),
+				name: t.name.clone(),
+				count: self.stats.completions.get(k).cloned().unwrap_or(0),
+			});
+		}
+		c
+	}
+
+	/// writes the machine in dot format with transitions `coverage` has never seen
+	/// executed drawn bold and red, the others labelled with their counts
+	pub fn render_coverage_dot<W: io::Write>(&self, w: &mut W,
+	                                         state2name: &HashMap<StateType, &'static str>,
+	                                         event2name: &HashMap<EventType, &'static str>,
+	                                         coverage: &Coverage<StateType, EventType>,
+	                                         options: &DotOptions,
+	) -> Result<(), io::Error> {
+		let mut g = self.dotgraph(state2name, event2name, options);
+		for (k, e) in g.edges.iter_mut() {
+			let count = coverage.count(k);
+			if count == 0 {
+				e.style = ::dot::Style::Bold;
+				e.color = Some(DOT_UNTESTED_COLOR);
+				e.label = format!("{}\nUNTESTED", e.label);
+			} else {
+				e.label = format!("{}\n{}x", e.label, count);
+			}
+		}
+		::dot::render(&g, w)
+	}
+
+	/// provides the coverage dot graph of `render_coverage_dot` as a string
+	pub fn coverage_dot_string(&self,
+	                           state2name: &HashMap<StateType, &'static str>,
+	                           event2name: &HashMap<EventType, &'static str>,
+	                           coverage: &Coverage<StateType, EventType>,
+	                           options: &DotOptions,
+	) -> Result<String, io::Error> {
+		render_string(|out| self.render_coverage_dot(out, state2name, event2name, coverage, options))
+	}
+}
@@ -0,0 +1,319 @@
+//! Async driver for FSMs, enabled by the `async` feature.
+//!
+//! `AsyncFSM` wraps an `FSM` and adds transitions whose closures return a future
+//! of the `TransitionResult`. The closure gets the extended state synchronously
+//! like any transition and has to return a future not borrowing it, the machine
+//! moves to the end state once the future resolved. `AsyncFSM::run` returns a
+//! future that takes events from a stream, e.g. the receiver of an async channel,
+//! and processes each of them together with all events queued by transitions
+//! before the next one is taken. Events posted through handles of the machine
+//! are taken as well, also while the stream has none.
+
+use std::cell::RefMut;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::pin::Pin;
+
+use futures::future::Future;
+use futures::stream::Stream;
+use futures::task::{Context, Poll};
+
+use super::{FSM, Errors, RunsFSM, OptionalFnArg, TransitionResult, TransitionSource,
+            TransitionTarget, TransitionKind};
+
+/// future returned by async transitions
+pub type TransitionFuture<EventType, StateType, TransitionFnArguments, ErrorType> =
+Pin<Box<dyn Future<Output = TransitionResult<EventType,
+	StateType,
+	TransitionFnArguments,
+	ErrorType>>>>;
+
+/// transition function running asynchronously, the returned future must not
+/// borrow the extended state
+pub type AsyncTransitionFn<ExtendedState, EventType, StateType, TransitionFnArguments, ErrorType> =
+dyn Fn(RefMut<Box<ExtendedState>>,
+	EventType,
+	OptionalFnArg<TransitionFnArguments>)
+	-> TransitionFuture<EventType, StateType, TransitionFnArguments, ErrorType>;
+
+/// map of from state/event to async transition
+type AsyncTransitionTable<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> =
+HashMap<TransitionSource<StateType, EventType>,
+	Box<AsyncTransitionFn<ExtendedState,
+		EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>>>;
+
+/// FSM with transitions running asynchronously, the synchronous side of the
+/// wrapped `FSM` is reached through `Deref`
+pub struct AsyncFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	fsm: FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+	transitions: AsyncTransitionTable<ExtendedState,
+		StateType,
+		EventType,
+		TransitionFnArguments,
+		ErrorType>,
+}
+
+/// async transition waiting for its future
+struct InFlight<EventType, StateType, TransitionFnArguments, ErrorType> {
+	state: StateType,
+	event: EventType,
+	endstate: StateType,
+	/// argument kept for the entry transition
+	kept: Option<TransitionFnArguments>,
+	future: TransitionFuture<EventType, StateType, TransitionFnArguments, ErrorType>,
+}
+
+/// event processed synchronously, async transition in flight or error
+type Started<EventType, StateType, TransitionFnArguments, ErrorType> =
+Result<Option<InFlight<EventType, StateType, TransitionFnArguments, ErrorType>>,
+	Errors<EventType, StateType, ErrorType>>;
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+AsyncFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      ErrorType: Debug
+{
+	/// wraps a machine which may have synchronous transitions already
+	pub fn new(fsm: FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>)
+	           -> AsyncFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		AsyncFSM {
+			fsm,
+			transitions: HashMap::new(),
+		}
+	}
+
+	/// new async transition, replacing any synchronous one on the same event in
+	/// the same state. The transition shows up in the dot output and the exports
+	/// like any other, `kind` tells whether it leaves the state if it ends in it.
+	///
+	/// `returns` - TRUE if transition has been inserted,
+	///             FALSE if a previous has been overwritten!
+	pub fn add_async_transition(&mut self,
+	                            from: TransitionSource<StateType, EventType>,
+	                            endstate: StateType,
+	                            kind: TransitionKind,
+	                            transfn: Box<AsyncTransitionFn<ExtendedState,
+		                            EventType,
+		                            StateType,
+		                            TransitionFnArguments,
+		                            ErrorType>>,
+	                            name: Option<&str>) -> bool {
+		// placeholder for the structure, never run by the driver
+		let placeholder = TransitionTarget::new(endstate,
+		                                        Box::new(|_, _, _| Err(Errors::TransitionFailure)),
+		                                        name)
+			.with_kind(kind);
+		self.transitions.insert(from.clone(), transfn);
+		self.fsm.add_transition(from, placeholder)
+	}
+
+	/// gives up the async transitions in exchange for the full `FSM`, running
+	/// any of them synchronously fails with `Errors::TransitionFailure`
+	pub fn into_inner(self) -> FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		self.fsm
+	}
+
+	/// future processing the events `events` yields until it ends
+	///
+	/// `returns` - number of events processed or the first error encountered,
+	///             on errors the machine should be shut down
+	pub fn run<St>(&mut self, events: St)
+	               -> Driver<'_, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType, St>
+		where St: Stream<Item = (EventType, OptionalFnArg<TransitionFnArguments>)> + Unpin
+	{
+		Driver {
+			fsm: self,
+			events,
+			inflight: None,
+			generation: 0,
+			left: 0,
+			processed: 0,
+		}
+	}
+
+	/// starts processing a single event, async transitions are returned in flight
+	fn start(&mut self, e: (EventType, OptionalFnArg<TransitionFnArguments>))
+	         -> Started<EventType, StateType, TransitionFnArguments, ErrorType> {
+		let state = self.fsm.current_state.clone();
+		let source = TransitionSource::new(state.clone(), e.0.clone());
+
+		let transfn = match self.transitions.get(&source) {
+			Some(transfn) => transfn,
+			None => {
+				return match self.fsm.process_event(e) {
+					Errors::OK => Ok(None),
+					r => Err(r),
+				};
+			}
+		};
+
+		let endstate = match self.fsm.transitions.get(&source) {
+			Some(itrans) => itrans.endstate.clone(),
+			None => return Err(Errors::TransitionFailure),
+		};
+
+		match self.fsm.leave(&source, &endstate, e.1.as_deref()) {
+			Errors::OK => {}
+			r => return Err(r),
+		}
+
+		let kept = self.fsm.keep(&e.1);
+		let future = transfn(self.fsm.extended_state.borrow_mut(), e.0.clone(), e.1);
+		Ok(Some(InFlight {
+			state,
+			event: e.0,
+			endstate,
+			kept,
+			future,
+		}))
+	}
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> Deref
+for AsyncFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	type Target = FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.fsm
+	}
+}
+
+/// future driving an `AsyncFSM` with the events of a stream, see `AsyncFSM::run`
+pub struct Driver<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType, St>
+	where ExtendedState: 'a,
+	      StateType: Clone + Eq + Hash + Sized + 'a,
+	      EventType: Clone + Eq + Hash + Sized + 'a,
+	      TransitionFnArguments: 'a,
+	      ErrorType: 'a
+{
+	fsm: &'a mut AsyncFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+	events: St,
+	inflight: Option<InFlight<EventType, StateType, TransitionFnArguments, ErrorType>>,
+	/// number of events queued when the generation being processed started, events
+	/// queued by its transitions make up the next one like with
+	/// `process_event_queue`, which is what the recorder is told about
+	generation: u32,
+	/// events of the generation not taken from the queue yet
+	left: u32,
+	processed: u32,
+}
+
+/// the future in flight is boxed and no other field is ever pinned
+impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType, St> Unpin
+for Driver<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType, St>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{}
+
+impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType, St> Future
+for Driver<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType, St>
+	where StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      ErrorType: Debug,
+	      St: Stream<Item = (EventType, OptionalFnArg<TransitionFnArguments>)> + Unpin
+{
+	type Output = Result<u32, Errors<EventType, StateType, ErrorType>>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		let this = self.get_mut();
+
+		loop {
+			// an async transition completes before anything else happens
+			if let Some(mut inflight) = this.inflight.take() {
+				let result = match inflight.future.as_mut().poll(cx) {
+					Poll::Pending => {
+						this.inflight = Some(inflight);
+						return Poll::Pending;
+					}
+					Poll::Ready(result) => result,
+				};
+				let res = this.fsm.fsm.complete(inflight.state, inflight.event,
+				                                inflight.endstate, inflight.kept, result);
+				if let Some(r) = this.failed(res) {
+					return Poll::Ready(r);
+				}
+			}
+
+			// then the queued events one by one, generation by generation
+			if this.left == 0 {
+				if this.generation > 0 {
+					if let Some(ref mut recorder) = this.fsm.fsm.recorder {
+						recorder.record_processed(&this.fsm.fsm.current_state,
+						                          Ok(this.generation));
+					}
+				}
+				this.generation = this.fsm.fsm.event_queue.len() as u32;
+				this.left = this.generation;
+			}
+			if let Some(e) = this.fsm.fsm.event_queue.pop_front() {
+				this.left -= 1;
+				this.processed += 1;
+				match this.fsm.start(e) {
+					Ok(inflight) => this.inflight = inflight,
+					Err(e) => {
+						if let Some(r) = this.failed(e) {
+							return Poll::Ready(r);
+						}
+					}
+				}
+				continue;
+			}
+
+			// and finally new events, posted through handles first
+			let mut posted = this.fsm.fsm.inbox.take();
+			if posted.is_empty() {
+				match Pin::new(&mut this.events).poll_next(cx) {
+					Poll::Ready(Some(e)) => posted.push(e),
+					Poll::Ready(None) => return Poll::Ready(Ok(this.processed)),
+					Poll::Pending => {
+						// handles wake the task as well, unless they posted
+						// before the waker has been registered
+						this.fsm.fsm.inbox.register(cx.waker());
+						if this.fsm.fsm.inbox.pending() == 0 {
+							return Poll::Pending;
+						}
+						continue;
+					}
+				}
+			}
+			if let Err(e) = this.fsm.fsm.add_events(&mut posted) {
+				return Poll::Ready(Err(e));
+			}
+		}
+	}
+}
+
+impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType, St>
+Driver<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType, St>
+	where StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      ErrorType: Debug
+{
+	/// records the state the failing machine is left in
+	///
+	/// `returns` - the output of the driver if `res` is an error
+	fn failed(&mut self, res: Errors<EventType, StateType, ErrorType>)
+	          -> Option<Result<u32, Errors<EventType, StateType, ErrorType>>> {
+		match res {
+			Errors::OK => None,
+			e => {
+				if let Some(ref mut recorder) = self.fsm.fsm.recorder {
+					recorder.record_processed(&self.fsm.fsm.current_state, Err(format!("{:?}", e)));
+				}
+				Some(Err(e))
+			}
+		}
+	}
+}
@@ -0,0 +1,264 @@
+//! Bounded exhaustive exploration of an FSM for tests.
+//!
+//! Starting from fresh machines built by a factory all sequences of a finite set
+//! of events are tried breadth first up to a depth bound. After every event the
+//! event queue is processed until it drains. With `explore` configurations (state,
+//! extended state and the states histories resume) already seen are not explored
+//! again, which needs a hashable extended state, `explore_all` follows every
+//! trace instead. Either way every reported trace is a shortest one leading to
+//! the problem.
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::{FSM, Errors, RunsFSM};
+
+/// invariant over state and extended state, returns a description of the violation
+pub type Invariant<ExtendedState, StateType> = dyn Fn(&StateType, &ExtendedState)
+	-> Result<(), String>;
+
+/// sequence of events with their arguments leading to a configuration
+pub type Trace<EventType, TransitionFnArguments> = Vec<(EventType, Option<TransitionFnArguments>)>;
+
+/// trace processing of which returned an error
+pub type FailedTrace<StateType, EventType, TransitionFnArguments, ErrorType> =
+	(Trace<EventType, TransitionFnArguments>, Errors<EventType, StateType, ErrorType>);
+
+/// builds a fresh machine, must be deterministic
+pub type Factory<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> =
+	dyn Fn() -> FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> + 'a;
+
+/// maximum number of `process_event_queue` rounds a single event may take
+pub const MAX_QUEUE_ROUNDS: usize = 1000;
+
+/// an invariant did not hold after the last event of `trace`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation<StateType, EventType, TransitionFnArguments> {
+	/// name the invariant has been added with
+	pub invariant: String,
+	/// what the invariant reported
+	pub message: String,
+	pub state: StateType,
+	pub trace: Trace<EventType, TransitionFnArguments>,
+}
+
+/// outcome of an exploration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplorationReport<StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Eq + Hash
+{
+	/// states reached without errors or violations
+	pub reachable: HashSet<StateType>,
+	/// shortest traces processing of which returned an error
+	pub errors: Vec<FailedTrace<StateType, EventType, TransitionFnArguments, ErrorType>>,
+	/// shortest traces violating an invariant
+	pub violations: Vec<Violation<StateType, EventType, TransitionFnArguments>>,
+	/// shortest traces after which the event queue did not drain within
+	/// `MAX_QUEUE_ROUNDS`
+	pub livelocks: Vec<Trace<EventType, TransitionFnArguments>>,
+	/// number of distinct configurations explored, of traces with `explore_all`
+	pub configurations: usize,
+	/// TRUE if configurations at the depth bound had not been explored further
+	pub bounded: bool,
+}
+
+impl<StateType, EventType, TransitionFnArguments, ErrorType>
+ExplorationReport<StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Eq + Hash
+{
+	/// `returns` - TRUE if neither errors, violations nor livelocks have been found
+	pub fn is_clean(&self) -> bool {
+		self.errors.is_empty() && self.violations.is_empty() && self.livelocks.is_empty()
+	}
+}
+
+/// explores the machines built by a factory
+pub struct Explorer<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	factory: Box<Factory<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>>,
+	events: Vec<(EventType, Option<TransitionFnArguments>)>,
+	depth: usize,
+	invariants: Vec<(String, Box<Invariant<ExtendedState, StateType>>)>,
+}
+
+/// result of running a trace on a fresh machine
+enum Outcome<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	Reached(Box<FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>>),
+	Failed(Errors<EventType, StateType, ErrorType>),
+	Livelock,
+}
+
+impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+Explorer<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      TransitionFnArguments: Clone,
+	      ErrorType: Debug
+{
+	/// new explorer
+	///   * `factory` - builds a fresh machine, must be deterministic
+	///   * `events`  - events with sample arguments tried in every configuration
+	///   * `depth`   - maximum number of events in a trace
+	pub fn new(factory: Box<Factory<'a,
+		           ExtendedState,
+		           StateType,
+		           EventType,
+		           TransitionFnArguments,
+		           ErrorType>>,
+	           events: Vec<(EventType, Option<TransitionFnArguments>)>,
+	           depth: usize)
+	           -> Explorer<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		Explorer {
+			factory,
+			events,
+			depth,
+			invariants: Vec::new(),
+		}
+	}
+
+	/// adds an invariant checked in every configuration reached
+	pub fn add_invariant(&mut self, name: &str, invariant: Box<Invariant<ExtendedState, StateType>>) {
+		self.invariants.push((String::from(name), invariant));
+	}
+
+	/// runs `trace` given as indices into the events on a fresh machine
+	fn run(&self, trace: &[usize])
+	       -> Outcome<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		let mut fsm = (self.factory)();
+
+		for i in trace.iter() {
+			let (ref event, ref arg) = self.events[*i];
+			if let Err(e) = fsm.add_events(&mut vec![(event.clone(), arg.clone().map(Box::new))]) {
+				return Outcome::Failed(e);
+			}
+			let mut rounds = 0;
+			while fsm.events_pending() {
+				if rounds == MAX_QUEUE_ROUNDS {
+					return Outcome::Livelock;
+				}
+				if let Err(e) = fsm.process_event_queue() {
+					return Outcome::Failed(e);
+				}
+				rounds += 1;
+			}
+		}
+
+		Outcome::Reached(Box::new(fsm))
+	}
+
+	fn trace(&self, trace: &[usize]) -> Trace<EventType, TransitionFnArguments> {
+		trace.iter().map(|i| self.events[*i].clone()).collect()
+	}
+
+	/// explores all event sequences up to the depth bound breadth first without
+	/// recognizing configurations seen before, the number of traces grows
+	/// exponentially with the depth
+	pub fn explore_all(&self)
+	                   -> ExplorationReport<StateType, EventType, TransitionFnArguments, ErrorType> {
+		self.search(|_| true)
+	}
+
+	/// explores breadth first, configurations `fresh` returns FALSE for are not
+	/// explored further
+	fn search<F>(&self, mut fresh: F)
+	             -> ExplorationReport<StateType, EventType, TransitionFnArguments, ErrorType>
+		where F: FnMut(&FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>) -> bool
+	{
+		let mut report = ExplorationReport {
+			reachable: HashSet::new(),
+			errors: Vec::new(),
+			violations: Vec::new(),
+			livelocks: Vec::new(),
+			configurations: 0,
+			bounded: false,
+		};
+		let mut frontier: Vec<Vec<usize>> = vec![Vec::new()];
+
+		for depth in 0..self.depth + 1 {
+			let mut next = Vec::new();
+
+			for trace in frontier.iter() {
+				let fsm = match self.run(trace) {
+					Outcome::Reached(fsm) => fsm,
+					Outcome::Failed(e) => {
+						report.errors.push((self.trace(trace), e));
+						continue;
+					}
+					Outcome::Livelock => {
+						report.livelocks.push(self.trace(trace));
+						continue;
+					}
+				};
+
+				if !fresh(&fsm) {
+					continue;
+				}
+				report.configurations += 1;
+
+				let state = fsm.current_state();
+				let extstate = fsm.extended_state();
+
+				let violations = self.invariants.iter().filter_map(|(name, inv)| {
+					inv(&state, &extstate).err().map(|message| {
+						Violation {
+							invariant: name.clone(),
+							message,
+							state: state.clone(),
+							trace: self.trace(trace),
+						}
+					})
+				}).collect::<Vec<_>>();
+				if !violations.is_empty() {
+					report.violations.extend(violations);
+					continue;
+				}
+				report.reachable.insert(state);
+
+				if depth == self.depth {
+					report.bounded = report.bounded || !self.events.is_empty();
+					continue;
+				}
+				for i in 0..self.events.len() {
+					let mut t = trace.clone();
+					t.push(i);
+					next.push(t);
+				}
+			}
+
+			frontier = next;
+		}
+
+		report
+	}
+}
+
+impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+Explorer<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where ExtendedState: Clone + Eq + Hash,
+	      StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      TransitionFnArguments: Clone,
+	      ErrorType: Debug
+{
+	/// explores all event sequences up to the depth bound breadth first, skipping
+	/// configurations seen before
+	pub fn explore(&self)
+	               -> ExplorationReport<StateType, EventType, TransitionFnArguments, ErrorType> {
+		let mut seen = HashSet::new();
+		// every machine built orders its histories differently
+		let mut histories: Option<Vec<StateType>> = None;
+		self.search(|fsm| {
+			let resumed = histories.get_or_insert_with(|| fsm.histories.keys().cloned().collect())
+				.iter()
+				.map(|h| fsm.histories.get(h).and_then(|history| history.last.clone()))
+				.collect::<Vec<_>>();
+			seen.insert((fsm.current_state(), (**fsm.extended_state()).clone(), resumed))
+		})
+	}
+}
@@ -0,0 +1,223 @@
+//! Flattened, name based view of the machine structure shared by the text
+//! exporters. Everything is sorted by name so exported text is stable.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+
+use super::{FSM, EntryExit, TransitionKind, HistoryKind};
+
+/// a transition upon an event with all states and events resolved to names
+pub struct ExportTransition<'a> {
+	pub from: &'static str,
+	pub event: &'static str,
+	pub to: &'static str,
+	pub name: Option<&'a str>,
+	/// self transition leaving and re-entering its state
+	pub external: bool,
+}
+
+/// an entry or exit transition of a state
+pub struct ExportEntryExit<'a> {
+	pub state: &'static str,
+	pub kind: EntryExit,
+	pub name: Option<&'a str>,
+}
+
+/// a branch of a choice pseudo-state
+pub struct ExportBranch<'a> {
+	pub choice: &'static str,
+	pub to: &'static str,
+	pub guard: Option<&'a str>,
+	/// branch taken if no guard holds
+	pub otherwise: bool,
+}
+
+impl<'a> ExportBranch<'a> {
+	/// guard in brackets as drawn on the branch, `[else]` if no guard holds
+	pub fn label(&self) -> String {
+		if self.otherwise {
+			String::from("[else]")
+		} else {
+			format!("[{}]", self.guard.unwrap_or(""))
+		}
+	}
+}
+
+/// a completion transition
+pub struct ExportCompletion<'a> {
+	pub from: &'static str,
+	pub to: &'static str,
+	pub name: Option<&'a str>,
+	/// None if the transition is not guarded, unnamed guards are empty
+	pub guard: Option<&'a str>,
+}
+
+impl<'a> ExportCompletion<'a> {
+	/// guard in brackets and name as drawn on the transition, empty if it has neither
+	pub fn label(&self) -> String {
+		let guard = self.guard.map(|g| format!("[{}]", g));
+		let name = self.name.map(|n| format!("/ {}", n));
+		guard.into_iter().chain(name).collect::<Vec<_>>().join(" ")
+	}
+}
+
+/// a history pseudo-state with the group of states it resumes
+pub struct ExportHistory {
+	pub history: &'static str,
+	pub kind: HistoryKind,
+	/// in the order they have been declared
+	pub members: Vec<&'static str>,
+	pub initial: &'static str,
+}
+
+impl ExportHistory {
+	/// `[H]` or `[H*]` as history pseudo-states are drawn in UML
+	pub fn marker(&self) -> &'static str {
+		match self.kind {
+			HistoryKind::Shallow => "[H]",
+			HistoryKind::Deep => "[H*]",
+		}
+	}
+}
+
+/// the whole machine structure by name
+pub struct ExportMachine<'a> {
+	pub name: &'a str,
+	pub start: &'static str,
+	pub states: Vec<&'static str>,
+	pub transitions: Vec<ExportTransition<'a>>,
+	pub entryexit: Vec<ExportEntryExit<'a>>,
+	/// branches by choice in the order they are tried
+	pub branches: Vec<ExportBranch<'a>>,
+	pub histories: Vec<ExportHistory>,
+	pub completions: Vec<ExportCompletion<'a>>,
+}
+
+impl<'a> ExportMachine<'a> {
+	/// entry or exit transitions of `state`
+	pub fn entryexit_of(&self, state: &str) -> Vec<&ExportEntryExit<'a>> {
+		self.entryexit.iter().filter(|ee| ee.state == state).collect()
+	}
+
+	/// branches of `state` if it is a choice
+	pub fn branches_of(&self, state: &str) -> Vec<&ExportBranch<'a>> {
+		self.branches.iter().filter(|b| b.choice == state).collect()
+	}
+
+	/// completion transition of `state` if it has one
+	pub fn completion_of(&self, state: &str) -> Option<&ExportCompletion<'a>> {
+		self.completions.iter().find(|c| c.from == state)
+	}
+
+	/// `state` as history pseudo-state if it is one
+	pub fn history_of(&self, state: &str) -> Option<&ExportHistory> {
+		self.histories.iter().find(|h| h.history == state)
+	}
+}
+
+/// turns a name into an identifier that can be used by the text formats
+pub fn export_id(name: &str) -> String {
+	name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+/// provides what `render` writes as a string
+pub fn render_string<F>(render: F) -> Result<String, io::Error>
+	where F: FnOnce(&mut Vec<u8>) -> Result<(), io::Error>
+{
+	let mut out = Vec::new();
+	render(&mut out)?;
+	String::from_utf8(out).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// name of `k` or "?" if the name maps do not contain it
+fn name_of<K: Eq + Hash>(names: &HashMap<K, &'static str>, k: &K) -> &'static str {
+	names.get(k).cloned().unwrap_or("?")
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	/// resolves the structure of the machine to names, states not in `state2name`
+	/// are only present under "?" if transitions refer to them
+	pub(crate) fn export(&self,
+	                     state2name: &HashMap<StateType, &'static str>,
+	                     event2name: &HashMap<EventType, &'static str>)
+	                     -> ExportMachine<'_> {
+		let mut states = state2name.values().cloned().collect::<Vec<_>>();
+		states.sort();
+		states.dedup();
+
+		// dynamic transitions are exported once per target
+		let mut transitions = self.transitions.iter().flat_map(|(tk, tv)| {
+			tv.targets.iter().map(move |target| ExportTransition {
+				from: name_of(state2name, &tk.state),
+				event: name_of(event2name, &tk.event),
+				to: name_of(state2name, target),
+				name: tv.name.as_deref(),
+				external: tk.state == *target && tv.kind == TransitionKind::External,
+			})
+		}).collect::<Vec<_>>();
+		transitions.sort_by(|a, b| (a.from, a.event, a.to).cmp(&(b.from, b.event, b.to)));
+
+		let mut entryexit = self.statetransitions.iter().map(|(tk, tv)| {
+			ExportEntryExit {
+				state: name_of(state2name, &tk.0),
+				kind: tk.1.clone(),
+				name: tv.1.as_deref(),
+			}
+		}).collect::<Vec<_>>();
+		entryexit.sort_by_key(|ee| (ee.state, ee.kind == EntryExit::ExitTransition));
+
+		// the sort is stable and keeps the branches of a choice in order
+		let mut branches = self.choices.iter().flat_map(|(c, choice)| {
+			choice.branches.iter()
+				.map(move |b| ExportBranch {
+					choice: name_of(state2name, c),
+					to: name_of(state2name, &b.target),
+					guard: b.name.as_deref(),
+					otherwise: false,
+				})
+				.chain(Some(ExportBranch {
+					choice: name_of(state2name, c),
+					to: name_of(state2name, &choice.otherwise),
+					guard: None,
+					otherwise: true,
+				}))
+		}).collect::<Vec<_>>();
+		branches.sort_by_key(|b| b.choice);
+
+		let mut histories = self.histories.iter().map(|(h, history)| {
+			ExportHistory {
+				history: name_of(state2name, h),
+				kind: history.kind,
+				members: history.members.iter().map(|m| name_of(state2name, m)).collect(),
+				initial: name_of(state2name, &history.initial),
+			}
+		}).collect::<Vec<_>>();
+		histories.sort_by_key(|h| h.history);
+
+		let mut completions = self.completions.iter().map(|(s, c)| {
+			ExportCompletion {
+				from: name_of(state2name, s),
+				to: name_of(state2name, &c.endstate),
+				name: c.name.as_deref(),
+				guard: c.guard.as_ref().map(|_| c.guard_name.as_deref().unwrap_or("")),
+			}
+		}).collect::<Vec<_>>();
+		completions.sort_by_key(|c| c.from);
+
+		ExportMachine {
+			name: &self.name,
+			start: name_of(state2name, &self.start_state),
+			states,
+			transitions,
+			entryexit,
+			branches,
+			histories,
+			completions,
+		}
+	}
+}
@@ -0,0 +1,83 @@
+//! Handles posting events into an FSM from other threads.
+//!
+//! Every machine owns an inbox that `FSM::handle` hands out clones of. Handles are
+//! `Send + Sync` whenever the events and arguments are `Send`, so I/O threads can
+//! post into machines they do not own. The thread running the machine moves the
+//! posted events behind the already queued ones at the start of every
+//! `process_event_queue`, exactly as if they had been passed to `add_events`.
+//! With the `async` feature posting also wakes the driver of the machine.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(feature = "async")]
+use futures::task::{AtomicWaker, Waker};
+
+use super::{EventQueue, OptionalFnArg};
+
+/// events posted and not taken yet, shared by all handles of a machine
+struct Inbox<EventType, TransitionFnArguments> {
+	events: Mutex<EventQueue<EventType, TransitionFnArguments>>,
+	/// task driving the machine waiting for events
+	#[cfg(feature = "async")]
+	waker: AtomicWaker,
+}
+
+/// cloneable handle posting events into the inbox of an FSM
+pub struct EventHandle<EventType, TransitionFnArguments> {
+	inbox: Arc<Inbox<EventType, TransitionFnArguments>>,
+}
+
+impl<EventType, TransitionFnArguments> Clone for EventHandle<EventType, TransitionFnArguments> {
+	fn clone(&self) -> EventHandle<EventType, TransitionFnArguments> {
+		EventHandle {
+			inbox: self.inbox.clone(),
+		}
+	}
+}
+
+impl<EventType, TransitionFnArguments> Default for EventHandle<EventType, TransitionFnArguments> {
+	fn default() -> EventHandle<EventType, TransitionFnArguments> {
+		EventHandle {
+			inbox: Arc::new(Inbox {
+				events: Mutex::new(EventQueue::new()),
+				#[cfg(feature = "async")]
+				waker: AtomicWaker::new(),
+			}),
+		}
+	}
+}
+
+impl<EventType, TransitionFnArguments> EventHandle<EventType, TransitionFnArguments> {
+	/// a thread panicking while posting cannot leave the queue inconsistent so
+	/// poisoning is ignored
+	fn lock(&self) -> MutexGuard<'_, EventQueue<EventType, TransitionFnArguments>> {
+		self.inbox.events.lock().unwrap_or_else(|e| e.into_inner())
+	}
+
+	/// posts events to be processed by the next `process_event_queue` of the machine
+	///
+	/// `returns` - number of events posted
+	pub fn post(&self, events: Vec<(EventType, OptionalFnArg<TransitionFnArguments>)>) -> u32 {
+		let el = events.len();
+		self.lock().extend(events);
+		#[cfg(feature = "async")]
+		self.inbox.waker.wake();
+		el as u32
+	}
+
+	/// `returns` - number of posted events the machine has not taken yet
+	pub fn pending(&self) -> usize {
+		self.lock().len()
+	}
+
+	/// takes all posted events
+	pub(crate) fn take(&self) -> Vec<(EventType, OptionalFnArg<TransitionFnArguments>)> {
+		self.lock().drain(..).collect()
+	}
+
+	/// `waker` is woken by the next post
+	#[cfg(feature = "async")]
+	pub(crate) fn register(&self, waker: &Waker) {
+		self.inbox.waker.register(waker);
+	}
+}
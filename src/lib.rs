@@ -6,6 +6,37 @@
 //! * each transition closure can return with vector of arguments that
 //!   are queued at the end of outstanding events queue
 //! * can generate dot represenation of itself
+//! * can export its structure as PlantUML or Mermaid state diagram and as SCXML
+//! * can be built from a description of its structure bound to named closures,
+//!   see `load`, or have its code generated from it at build time, see `codegen`
+//! * externally added events can be recorded and replayed deterministically,
+//!   see `replay`
+//! * all event sequences up to a depth can be explored exhaustively in tests,
+//!   checking invariants, see `explore`, or randomly with shrinking of failing
+//!   sequences with the `proptest` feature, see `property`
+//! * transitions executed by a test suite can be checked for complete coverage,
+//!   see `coverage`
+//! * scripted scenarios check state, queued events and extended state step by
+//!   step, see `scenario`
+//! * machines built from `Send` closures can be moved across threads, see `send`
+//! * other threads can post events through handles, see `handle`
+//! * with the `async` feature transitions can run asynchronously and machines be
+//!   driven by a stream of events, see `driver`
+//! * many machines can be run together exchanging events, see `runtime`
+//! * transitions can spawn child machines and parents get notified when they end
+//! * failing machines can be restarted from a checkpoint, see `supervisor`
+//! * transitions can choose their end state out of declared targets, see
+//!   `TransitionTarget::dynamic`
+//! * choice pseudo-states branch on guards over the extended state within the
+//!   same step, see `FSM::add_choice`
+//! * shallow and deep history pseudo-states resume the state of a possibly
+//!   nested group the machine has been in last, see `FSM::add_history`
+//! * self transitions are internal unless declared external, which runs exit and
+//!   entry transitions, see `TransitionKind`
+//! * completion transitions leave a state right after its entry without an
+//!   event, optionally guarded, see `FSM::add_completion`
+//! * entry and exit transitions can see the event, states and argument of the
+//!   transition they are triggered by, see `EntryExitContext`
 //!
 //! # Author
 //! Tony Przygienda, 2016
@@ -41,12 +72,21 @@
 extern crate slog;
 extern crate dot;
 extern crate uuid;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "proptest")]
+extern crate proptest;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(test)]
 #[macro_use]
 extern crate custom_derive;
+#[cfg(test)]
 #[macro_use]
 extern crate enum_derive;
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::cell::{RefMut, RefCell, Ref};
 use std::hash::Hash;
 use std::fmt::Debug;
@@ -57,13 +97,59 @@ use std::io;
 use std::fs;
 use uuid::Uuid;
 
+pub mod replay;
+pub mod load;
+pub mod codegen;
+pub mod explore;
+pub mod coverage;
+pub mod scenario;
+pub mod send;
+pub mod handle;
+#[cfg(feature = "async")]
+pub mod driver;
+pub mod runtime;
+pub mod supervisor;
+#[cfg(feature = "proptest")]
+pub mod property;
+mod export;
+mod plantuml;
+mod mermaid;
+mod scxml;
+
+use replay::RecordsFSM;
+use handle::EventHandle;
+use export::render_string;
+
 /// types of transitions on states
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum EntryExit {
 	EntryTransition,
 	ExitTransition,
 }
 
+/// whether a transition ending in the state it starts from leaves that state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TransitionKind {
+	/// remains in the state, neither exit nor entry transition are run
+	#[default]
+	Internal,
+	/// leaves and re-enters the state, runs exit then entry transition
+	External,
+}
+
+/// what a history pseudo-state resumes when its group contains nested groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HistoryKind {
+	/// the member last been in, nested groups are entered at their initial state
+	#[default]
+	Shallow,
+	/// the state last been in, however deeply nested
+	Deep,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// Errors that can occur when running FSMs
 pub enum Errors<EventType, StateType, ErrorType> {
@@ -72,7 +158,11 @@ pub enum Errors<EventType, StateType, ErrorType> {
 	InternalError(EventType, StateType, ErrorType),
 	/// the requested transition does not exist, FSM needs to be shut down
 	NoTransition(EventType, StateType),
-	/// transition failed, you have to shut down the FSM
+	/// dynamic transition upon the event in the state chose the last state which it
+	/// has not been declared with, FSM needs to be shut down
+	InvalidTarget(EventType, StateType, StateType),
+	/// transition failed, you have to shut down the FSM or have it restarted by a
+	/// `supervisor::Supervisor`
 	TransitionFailure,
 }
 
@@ -92,18 +182,61 @@ Result<Option<EventQueue<EventType, TransitionFnArguments>>,
 /// transition function used, takes optional argument and returns either with error
 /// or an optional set of events to be added to processing (at the end of event queue)
 pub type TransitionFn<ExtendedState, EventType, StateType, TransitionFnArguments, ErrorType> =
-Fn(RefMut<Box<ExtendedState>>,
+dyn Fn(RefMut<Box<ExtendedState>>,
 	EventType,
 	OptionalFnArg<TransitionFnArguments>)
 	-> TransitionResult<EventType, StateType, TransitionFnArguments, ErrorType>;
 
+/// to be returned by dynamic transitions, the state the machine ends up in and
+/// an optional queue of events to be added to the FSM or an error
+pub type DynamicTransitionResult<EventType, StateType, TransitionFnArguments, ErrorType> =
+Result<(StateType, Option<EventQueue<EventType, TransitionFnArguments>>),
+	Errors<EventType, StateType, ErrorType>>;
+
+/// transition function choosing the end state out of the targets declared with it
+pub type DynamicTransitionFn<ExtendedState, EventType, StateType, TransitionFnArguments, ErrorType> =
+dyn Fn(RefMut<Box<ExtendedState>>,
+	EventType,
+	OptionalFnArg<TransitionFnArguments>)
+	-> DynamicTransitionResult<EventType, StateType, TransitionFnArguments, ErrorType>;
+
+/// guard of a choice branch over the extended state
+pub type Guard<ExtendedState> = dyn Fn(&ExtendedState) -> bool;
+
 /// transition function to either enter or exit a specific state, return same as
 /// `FSMTransitionFn`
 pub type EntryExitTransitionFn<ExtendedState,
                                EventType,
                                StateType,
                                TransitionFnArguments,
-                               ErrorType> = Fn(RefMut<Box<ExtendedState>>)
+                               ErrorType> = dyn Fn(RefMut<Box<ExtendedState>>)
+	-> TransitionResult<EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>;
+
+/// what an entry or exit transition has been triggered by
+pub struct EntryExitContext<'a, StateType: 'a, EventType: 'a, TransitionFnArguments: 'a> {
+	/// event of the transition, None for completion transitions
+	pub event: Option<&'a EventType>,
+	/// state the machine leaves
+	pub from: &'a StateType,
+	/// state the machine enters, on exit it may still be a choice or history
+	/// pseudo-state the machine passes through or the first target of a dynamic
+	/// transition that has not chosen yet
+	pub to: &'a StateType,
+	/// argument the event has been posted with, see `FSM::add_enter_transition_with_context`
+	pub arg: Option<&'a TransitionFnArguments>,
+}
+
+/// transition function to either enter or exit a specific state seeing what it
+/// has been triggered by, return same as `FSMTransitionFn`
+pub type ContextEntryExitTransitionFn<ExtendedState,
+                                      EventType,
+                                      StateType,
+                                      TransitionFnArguments,
+                                      ErrorType> =
+dyn Fn(RefMut<Box<ExtendedState>>, &EntryExitContext<StateType, EventType, TransitionFnArguments>)
 	-> TransitionResult<EventType,
 		StateType,
 		TransitionFnArguments,
@@ -114,7 +247,7 @@ pub type EntryExitTransitionFn<ExtendedState,
 /// # Template parameters
 ///
 ///  * `ExtendedState` - provides a structure that every transition can access and
-///                      stores extended state
+///    stores extended state
 ///  * `TransitionFnArguments` - type that can be boxed as parameters to an event instance
 ///  * `ErrorType` - Errors that transitions can generate internally
 pub struct FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
@@ -123,6 +256,7 @@ pub struct FSM<ExtendedState, StateType, EventType, TransitionFnArguments, Error
 {
 	name: String,
 	pub extended_state: RefCell<Box<ExtendedState>>,
+	start_state: StateType,
 	current_state: StateType,
 	event_queue: EventQueue<EventType, TransitionFnArguments>,
 	transitions: TransitionTable<ExtendedState,
@@ -137,17 +271,154 @@ pub struct FSM<ExtendedState, StateType, EventType, TransitionFnArguments, Error
 		ErrorType>,
 	log: Logger,
 
-	/// dotgraph structure for output
-	dotgraph: DotGraph<StateType, EventType>,
+	/// optional observer of externally added events and resulting states
+	recorder: Option<Box<dyn RecordsFSM<EventType, StateType, TransitionFnArguments>>>,
+
+	/// which transitions have been taken how often
+	stats: TransitionStats<StateType, EventType>,
+
+	/// events posted through handles, possibly from other threads
+	inbox: EventHandle<EventType, TransitionFnArguments>,
+
+	/// choice pseudo-states with their branches
+	choices: HashMap<StateType, Choice<ExtendedState, StateType>>,
+
+	/// history pseudo-states with the group of states they resume
+	histories: HashMap<StateType, History<StateType>>,
+
+	/// completion transitions per state
+	completions: HashMap<StateType, CompletionTransition<ExtendedState,
+		StateType,
+		EventType,
+		TransitionFnArguments,
+		ErrorType>>,
+
+	/// copies event arguments for entry transitions, set once one of them takes
+	/// the context
+	keep_arg: Option<fn(&TransitionFnArguments) -> TransitionFnArguments>,
+}
+
+/// default number of most recent transitions the FSM remembers
+pub const DEFAULT_RECENT_TRANSITIONS: usize = 16;
+
+/// maximum number of completion transitions taken one after the other, more fail
+/// with `Errors::TransitionFailure` since they are taken to loop
+pub const MAX_COMPLETIONS: usize = 100;
+
+/// counters of transitions taken by the FSM, used to annotate the dot output
+struct TransitionStats<StateType, EventType>
+	where StateType: Clone + Sized + Eq + Hash,
+	      EventType: Clone + Sized + Eq + Hash
+{
+	transitions: HashMap<TransitionEdge<StateType, EventType>, u64>,
+	entryexit: HashMap<EntryExitKey<StateType>, u64>,
+	/// branches taken out of choice pseudo-states
+	choices: HashMap<ChoiceKey<StateType>, u64>,
+	/// completion transitions taken per state
+	completions: HashMap<StateType, u64>,
+	/// most recent transition at the back
+	recent: VecDeque<TransitionEdge<StateType, EventType>>,
+	recent_len: usize,
+}
+
+impl<StateType, EventType> Default for TransitionStats<StateType, EventType>
+	where StateType: Clone + Sized + Eq + Hash,
+	      EventType: Clone + Sized + Eq + Hash
+{
+	fn default() -> TransitionStats<StateType, EventType> {
+		TransitionStats {
+			transitions: HashMap::new(),
+			entryexit: HashMap::new(),
+			choices: HashMap::new(),
+			completions: HashMap::new(),
+			recent: VecDeque::new(),
+			recent_len: DEFAULT_RECENT_TRANSITIONS,
+		}
+	}
+}
+
+impl<StateType, EventType> TransitionStats<StateType, EventType>
+	where StateType: Clone + Sized + Eq + Hash,
+	      EventType: Clone + Sized + Eq + Hash
+{
+	fn transition_taken(&mut self, t: &TransitionEdge<StateType, EventType>) {
+		*self.transitions.entry(t.clone()).or_insert(0) += 1;
+		if self.recent_len > 0 {
+			if self.recent.len() == self.recent_len {
+				self.recent.pop_front();
+			}
+			self.recent.push_back(t.clone());
+		}
+	}
+
+	fn entryexit_taken(&mut self, k: &EntryExitKey<StateType>) {
+		*self.entryexit.entry(k.clone()).or_insert(0) += 1;
+	}
+
+	fn choice_taken(&mut self, k: &ChoiceKey<StateType>) {
+		*self.choices.entry(k.clone()).or_insert(0) += 1;
+	}
+
+	fn completion_taken(&mut self, s: &StateType) {
+		*self.completions.entry(s.clone()).or_insert(0) += 1;
+	}
+
+	fn count(&self, k: &DotEdgeKey<StateType, EventType>) -> u64 {
+		match *k {
+			DotEdgeKey::Transition(ref t) => self.transitions.get(t),
+			DotEdgeKey::EntryExit(ref eek) => self.entryexit.get(eek),
+			DotEdgeKey::Choice(ref ck) => self.choices.get(ck),
+			DotEdgeKey::Completion(ref s) => self.completions.get(s),
+		}.cloned().unwrap_or(0)
+	}
+
+	/// position of the transition counting backwards from the most recent one
+	/// which is 1, if it is within the last `n` transitions taken
+	fn recent_position(&self, k: &DotEdgeKey<StateType, EventType>, n: usize) -> Option<usize> {
+		match *k {
+			DotEdgeKey::Transition(ref t) => {
+				self.recent.iter().rev().take(n).position(|r| r == t).map(|p| p + 1)
+			}
+			_ => None,
+		}
+	}
+}
+
+/// options to annotate the dot output of a running FSM
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DotOptions {
+	/// fill the node of the state the machine is currently in
+	pub highlight_current: bool,
+	/// color transitions by how often they have been taken and add the count to the label
+	pub color_by_frequency: bool,
+	/// mark the last N transitions taken, numbered from the most recent one.
+	/// Limited by the number of transitions the machine remembers, see
+	/// `FSM::set_recent_transitions`
+	pub recent_transitions: usize,
+	/// derive graph and node ids from the machine and state names and emit nodes
+	/// and edges sorted so the same machine always renders the same text.
+	/// States should have distinct names for this to be stable.
+	pub deterministic: bool,
 }
 
+/// colors used for transitions taken from rarely to most often
+const DOT_FREQUENCY_COLORS: [&str; 4] = ["blue", "darkgreen", "orange", "red"];
+/// color of transitions never taken when coloring by frequency
+const DOT_UNTAKEN_COLOR: &str = "gray";
+/// color of the most recent transitions taken
+const DOT_RECENT_COLOR: &str = "magenta";
+/// fill color of the current state
+const DOT_CURRENT_COLOR: &str = "lightblue";
+
 #[derive(Clone, PartialEq, Eq, Hash)]
 enum DotEdgeKey<StateType, EventType>
 	where StateType: Clone + Sized + Eq + Hash,
 	      EventType: Clone + Sized + Eq + Hash
 {
-	Transition(TransitionSource<StateType, EventType>),
+	Transition(TransitionEdge<StateType, EventType>),
 	EntryExit(EntryExitKey<StateType>),
+	Choice(ChoiceKey<StateType>),
+	Completion(StateType),
 }
 
 /// internal edge to generate DOT graphical view
@@ -157,7 +428,10 @@ struct DotEdge<StateType, EventType>
 	      EventType: Clone + Sized + Eq + Hash
 {
 	key: DotEdgeKey<StateType, EventType>,
+	source: DotNodeKey<StateType>,
+	target: DotNodeKey<StateType>,
 	style: dot::Style,
+	color: Option<&'static str>,
 	label: String,
 }
 
@@ -173,9 +447,10 @@ struct DotNode<StateType>
 	/// otherwise it's a "shadow node" that does not show up but can be used for
 	/// entry-exit transition annotation
 	key: DotNodeKey<StateType>,
-	id: Uuid,
+	id: String,
 	shape: Option<String>,
 	style: dot::Style,
+	color: Option<&'static str>,
 	label: &'static str,
 }
 
@@ -186,9 +461,9 @@ struct DotGraph<StateType, EventType>
 {
 	nodes: HashMap<DotNodeKey<StateType>, DotNode<StateType>>,
 	edges: HashMap<DotEdgeKey<StateType, EventType>, DotEdge<StateType, EventType>>,
-	id: Uuid,
-	/// starting state of FSM
-	start_state: Option<StateType>,
+	id: String,
+	/// emit nodes and edges sorted by their ids instead of in hash order
+	sorted: bool,
 }
 
 impl<StateType, EventType> Default for DotGraph<StateType, EventType>
@@ -199,97 +474,104 @@ impl<StateType, EventType> Default for DotGraph<StateType, EventType>
 		DotGraph {
 			nodes: HashMap::new(),
 			edges: HashMap::new(),
-			id: Uuid::new_v4(),
-			start_state: None
+			id: format!("G{}", Uuid::new_v4().simple()),
+			sorted: false,
+		}
+	}
+}
+
+impl<StateType, EventType> DotGraph<StateType, EventType>
+	where StateType: Clone + Sized + Eq + Hash,
+	      EventType: Clone + Sized + Eq + Hash
+{
+	/// id of a node, random unless stable ids are generated
+	fn node_id(&self, n: &DotNodeKey<StateType>) -> &str {
+		match self.nodes.get(n) {
+			Some(realnode) => realnode.id.as_str(),
+			None => unreachable!(),
 		}
 	}
 }
 
+/// turns `name` into a valid dot identifier starting with `prefix`
+fn dot_stable_id(prefix: &str, name: &str) -> String {
+	let mut id = String::from(prefix);
+	id.extend(name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }));
+	id
+}
+
 /// graphwalk
-impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+impl<'a, StateType, EventType>
 dot::GraphWalk<'a, DotNodeKey<StateType>, DotEdgeKey<StateType, EventType>>
-for FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+for DotGraph<StateType, EventType>
 	where StateType: Clone + PartialEq + Eq + Hash + Sized,
 	      EventType: Clone + PartialEq + Eq + Hash + Sized,
 {
 	fn nodes(&'a self) -> dot::Nodes<'a, DotNodeKey<StateType>> {
-		self.dotgraph.nodes.keys().cloned().collect()
+		let mut nodes = self.nodes.keys().cloned().collect::<Vec<_>>();
+		if self.sorted {
+			nodes.sort_by(|a, b| self.node_id(a).cmp(self.node_id(b)));
+		}
+		nodes.into()
 	}
 
 	fn edges(&'a self) -> dot::Edges<'a, DotEdgeKey<StateType, EventType>> {
-		self.dotgraph.edges.keys().cloned().collect()
+		let mut edges = self.edges.keys().cloned().collect::<Vec<_>>();
+		if self.sorted {
+			edges.sort_by(|a, b| {
+				let ea = &self.edges[a];
+				let eb = &self.edges[b];
+				(self.node_id(&ea.source), self.node_id(&ea.target), &ea.label)
+					.cmp(&(self.node_id(&eb.source), self.node_id(&eb.target), &eb.label))
+			});
+		}
+		edges.into()
 	}
 
 	fn source(&self, e: &DotEdgeKey<StateType, EventType>)
 	          -> DotNodeKey<StateType> {
-		match e {
-			&DotEdgeKey::EntryExit(ref eek) => {
-				if eek.1 == EntryExit::EntryTransition {
-					DotNodeKey(Some(eek.1.clone()), eek.0.clone())
-				} else {
-					if let Some(_) = self.statetransitions.get(eek) {
-						DotNodeKey(None, eek.0.clone())
-					} else {
-						unreachable!();
-					}
-				}
-			}
-			&DotEdgeKey::Transition(ref tk) => {
-				DotNodeKey(None, tk.state.clone())
+		match self.edges.get(e) {
+			Some(realedge) => {
+				realedge.source.clone()
 			}
+			None => unreachable!(),
 		}
 	}
+
 	fn target(&self, e: &DotEdgeKey<StateType, EventType>)
 	          -> DotNodeKey<StateType> {
-		// target more tricky, we have to lookup the real table
-		match e {
-			&DotEdgeKey::EntryExit(ref eek) => {
-				if eek.1 == EntryExit::ExitTransition {
-					DotNodeKey(Some(eek.1.clone()), eek.0.clone())
-				} else {
-					if let Some(_) = self.statetransitions.get(eek) {
-						DotNodeKey(None, eek.0.clone())
-					} else {
-						unreachable!();
-					}
-				}
-			}
-			&DotEdgeKey::Transition(ref tk) => {
-				if let Some(dn) = self.transitions.get(tk) {
-					DotNodeKey(None, dn.endstate.clone())
-				} else {
-					unreachable!();
-				}
+		match self.edges.get(e) {
+			Some(realedge) => {
+				realedge.target.clone()
 			}
+			None => unreachable!(),
 		}
 	}
 }
 
 /// graph labelling
-impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+impl<'a, StateType, EventType>
 dot::Labeller<'a, DotNodeKey<StateType>, DotEdgeKey<StateType, EventType>>
-for FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+for DotGraph<StateType, EventType>
 	where StateType: Clone + PartialEq + Eq + Hash + Sized,
 	      EventType: Clone + PartialEq + Eq + Hash + Sized,
 {
 	fn graph_id(&'a self) -> dot::Id<'a> {
-		let gid = format!("G{}", self.dotgraph.id.simple());
-		dot::Id::new(gid).unwrap()
+		dot::Id::new(self.id.as_str()).unwrap()
 	}
 
 	fn node_id(&'a self, n: &DotNodeKey<StateType>) -> dot::Id<'a> {
-		/// get the node
-		match self.dotgraph.nodes.get(n) {
+		// get the node
+		match self.nodes.get(n) {
 			Some(realnode) => {
-				let fid = format!("N{}", realnode.id.simple());
-				dot::Id::new(fid).unwrap()
+				dot::Id::new(realnode.id.as_str()).unwrap()
 			}
 			None => unreachable!(),
 		}
 	}
 
 	fn node_shape(&'a self, n: &DotNodeKey<StateType>) -> Option<dot::LabelText<'a>> {
-		match self.dotgraph.nodes.get(n) {
+		match self.nodes.get(n) {
 			Some(realnode) => {
 				if let Some(ref r) = realnode.shape {
 					Some(dot::LabelText::LabelStr(r.as_str().into()))
@@ -302,7 +584,7 @@ for FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
 	}
 
 	fn node_style(&'a self, n: &DotNodeKey<StateType>) -> dot::Style {
-		match self.dotgraph.nodes.get(n) {
+		match self.nodes.get(n) {
 			Some(realnode) => {
 				realnode.style
 			}
@@ -318,12 +600,35 @@ for FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
 		dot::Arrow::none()
 	}
 
-	fn edge_style(&'a self, _e: &DotEdgeKey<StateType, EventType>) -> dot::Style {
-		dot::Style::None
+	fn edge_style(&'a self, e: &DotEdgeKey<StateType, EventType>) -> dot::Style {
+		match self.edges.get(e) {
+			Some(realedge) => {
+				realedge.style
+			}
+			None => unreachable!(),
+		}
+	}
+
+	fn node_color(&'a self, n: &DotNodeKey<StateType>) -> Option<dot::LabelText<'a>> {
+		match self.nodes.get(n) {
+			Some(realnode) => {
+				realnode.color.map(|c| dot::LabelText::LabelStr(c.into()))
+			}
+			None => unreachable!(),
+		}
+	}
+
+	fn edge_color(&'a self, e: &DotEdgeKey<StateType, EventType>) -> Option<dot::LabelText<'a>> {
+		match self.edges.get(e) {
+			Some(realedge) => {
+				realedge.color.map(|c| dot::LabelText::LabelStr(c.into()))
+			}
+			None => unreachable!(),
+		}
 	}
 
 	fn node_label<'b>(&'b self, n: &DotNodeKey<StateType>) -> dot::LabelText<'b> {
-		match self.dotgraph.nodes.get(n) {
+		match self.nodes.get(n) {
 			Some(realnode) => {
 				dot::LabelText::LabelStr(realnode.label.into())
 			}
@@ -332,7 +637,7 @@ for FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
 	}
 
 	fn edge_label<'b>(&'b self, ek: &DotEdgeKey<StateType, EventType>) -> dot::LabelText<'b> {
-		match self.dotgraph.edges.get(ek) {
+		match self.edges.get(ek) {
 			Some(realedge) => {
 				dot::LabelText::LabelStr(realedge.label.clone().into())
 			}
@@ -373,142 +678,289 @@ FSM<ExtendedState,
 	           name: &str,
 	           log: Logger)
 	           -> FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
-		let mut g = DotGraph::default();
-		g.start_state = Some(start_state.clone());
-
 		FSM {
-			log: log,
+			log,
 			name: String::from(name),
+			start_state: start_state.clone(),
 			current_state: start_state,
 			event_queue: VecDeque::<(EventType, OptionalFnArg<TransitionFnArguments>)>::new(),
 			transitions: TransitionTable::new(),
 			statetransitions: EntryExitTransitionTable::new(),
 			extended_state: RefCell::new(extended_init),
-			dotgraph: g,
+			recorder: None,
+			stats: TransitionStats::default(),
+			inbox: EventHandle::default(),
+			choices: HashMap::new(),
+			histories: HashMap::new(),
+			completions: HashMap::new(),
+			keep_arg: None,
+		}
+	}
+
+	/// sets how many of the most recent transitions taken the machine remembers
+	/// for `DotOptions::recent_transitions`, `DEFAULT_RECENT_TRANSITIONS` unless set
+	pub fn set_recent_transitions(&mut self, n: usize) {
+		self.stats.recent_len = n;
+		while self.stats.recent.len() > n {
+			self.stats.recent.pop_front();
 		}
 	}
 
 	/// provides output of the FSM in dot format
 	///
-	///   * `filename` - optional filename
-	pub fn dotfile(&mut self, filename: Option<String>,
+	///   * `filename` - optional filename, output goes to stdout otherwise
+	pub fn dotfile(&self, filename: Option<String>,
 	               state2name: &HashMap<StateType, &'static str>,
 	               event2name: &HashMap<EventType, &'static str>,
 	) -> Result<(), io::Error> {
-		let fileattempt = if let Some(fname) = filename {
-			fs::File::create(fname).map(|f| Some(f))
-		} else {
-			Ok(None)
-		};
+		self.dotfile_with_options(filename, state2name, event2name, &DotOptions::default())
+	}
+
+	/// provides output of the FSM in dot format annotated with the runtime
+	/// information requested in `options`
+	///
+	///   * `filename` - optional filename, output goes to stdout otherwise
+	pub fn dotfile_with_options(&self, filename: Option<String>,
+	                            state2name: &HashMap<StateType, &'static str>,
+	                            event2name: &HashMap<EventType, &'static str>,
+	                            options: &DotOptions,
+	) -> Result<(), io::Error> {
+		match filename {
+			Some(fname) => {
+				let mut f = fs::File::create(fname)?;
+				self.render_dot(&mut f, state2name, event2name, options)
+			}
+			None => self.render_dot(&mut io::stdout(), state2name, event2name, options),
+		}
+	}
+
+	/// writes the FSM in dot format annotated per `options` into any writer
+	pub fn render_dot<W: io::Write>(&self, w: &mut W,
+	                                state2name: &HashMap<StateType, &'static str>,
+	                                event2name: &HashMap<EventType, &'static str>,
+	                                options: &DotOptions,
+	) -> Result<(), io::Error> {
+		dot::render(&self.dotgraph(state2name, event2name, options), w)
+	}
 
-		if let Ok(maybef) = fileattempt {
-			let sout = io::stdout();
+	/// provides the FSM in dot format annotated per `options` as a string
+	pub fn dot_string(&self,
+	                  state2name: &HashMap<StateType, &'static str>,
+	                  event2name: &HashMap<EventType, &'static str>,
+	                  options: &DotOptions,
+	) -> Result<String, io::Error> {
+		render_string(|out| self.render_dot(out, state2name, event2name, options))
+	}
 
-			let sv = state2name.keys().cloned().collect::<Vec<_>>();
+	/// builds the dot graph of the machine in its current state
+	fn dotgraph(&self,
+	            state2name: &HashMap<StateType, &'static str>,
+	            event2name: &HashMap<EventType, &'static str>,
+	            options: &DotOptions,
+	) -> DotGraph<StateType, EventType> {
+		let mut g = DotGraph::default();
+		let mut used_ids = HashSet::new();
 
-			// generate the graph, nodes first
-			for n in sv.iter() {
-				// first _real_ nodes, i.e. not entry/exit
-				let key = DotNodeKey(None, n.clone());
+		let mut sv = state2name.keys().cloned().collect::<Vec<_>>();
 
-				let shape = if let Some(ref sn) = self.dotgraph.start_state {
-					if sn == n {
-						Some(String::from("diamond"))
-					} else {
-						None
-					}
-				} else {
-					None
-				};
+		if options.deterministic {
+			g.id = dot_stable_id("G_", &self.name);
+			g.sorted = true;
+			// ids of equally named states are disambiguated in order
+			sv.sort_by_key(|s| state2name.get(s));
+		}
 
-				self.dotgraph.nodes.insert(key.clone(),
-				                           DotNode {
-					                           key: key,
-					                           id: Uuid::new_v4(),
-					                           shape: shape,
-					                           style: dot::Style::None,
-					                           label: state2name.get(n).unwrap_or(&"?"),
-				                           }
-				);
+		// ids are either random or derived from the state name, suffixed if that
+		// name has already been taken
+		let mut node_id = |name: &str, suffix: &str| {
+			if options.deterministic {
+				let base = dot_stable_id("N_", &format!("{}{}", name, suffix));
+				let mut id = base.clone();
+				let mut i = 1;
+				while !used_ids.insert(id.clone()) {
+					i += 1;
+					id = format!("{}_{}", base, i);
+				}
+				id
+			} else {
+				format!("N{}", Uuid::new_v4().simple())
+			}
+		};
 
-				// now, let's generate pseudo nodes if necessary with entry, exit with
-				// invisible shapes
-
-				for t in
-					[EntryExit::EntryTransition,
-						EntryExit::ExitTransition]
-						.into_iter()
-						.collect::<Vec<_>>()
-						.into_iter() {
-					let eek = (n.clone(), t.clone());
-
-					match self.statetransitions.get(&eek) {
-						None => {}
-						Some(_) => {
-							let label = match t {
-								&EntryExit::EntryTransition => { "Enter".into() }
-								&EntryExit::ExitTransition => { "Exit".into() }
-							};
-							let key = DotNodeKey(Some(t.clone()), n.clone());
-							self.dotgraph.nodes.insert(key.clone(),
-							                           DotNode {
-								                           key: key,
-								                           id: Uuid::new_v4(),
-								                           shape: Some(String::from("plain")),
-								                           style: dot::Style::Dashed,
-								                           label: label,
-							                           });
-						}
+		// generate the graph, nodes first
+		for n in sv.iter() {
+			// first _real_ nodes, i.e. not entry/exit
+			let key = DotNodeKey(None, n.clone());
+
+			let shape = if *n == self.start_state || self.choices.contains_key(n) {
+				Some(String::from("diamond"))
+			} else if self.histories.contains_key(n) {
+				Some(String::from("circle"))
+			} else {
+				None
+			};
+
+			let (style, color) = if options.highlight_current && *n == self.current_state {
+				(dot::Style::Filled, Some(DOT_CURRENT_COLOR))
+			} else {
+				(dot::Style::None, None)
+			};
+
+			g.nodes.insert(key.clone(),
+			               DotNode {
+				               key,
+				               id: node_id(state2name.get(n).unwrap_or(&"?"), ""),
+				               shape,
+				               style,
+				               color,
+				               label: state2name.get(n).unwrap_or(&"?"),
+			               }
+			);
+
+			// now, let's generate pseudo nodes if necessary with entry, exit with
+			// invisible shapes
+
+			for t in
+				[EntryExit::EntryTransition,
+					EntryExit::ExitTransition]
+					.iter() {
+				let eek = (n.clone(), t.clone());
+
+				match self.statetransitions.get(&eek) {
+					None => {}
+					Some(_) => {
+						let label = match *t {
+							EntryExit::EntryTransition => { "Enter" }
+							EntryExit::ExitTransition => { "Exit" }
+						};
+						let key = DotNodeKey(Some(t.clone()), n.clone());
+						g.nodes.insert(key.clone(),
+						               DotNode {
+							               key,
+							               id: node_id(state2name.get(n).unwrap_or(&"?"),
+							                           &format!("_{}", label)),
+							               shape: Some(String::from("plain")),
+							               style: dot::Style::Dashed,
+							               color: None,
+							               label,
+						               });
 					}
 				}
 			}
+		}
 
-			// generate the edges now & label them
-			for t in self.transitions.iter() {
-				let (tk, tv) = t;
-
-				let key = DotEdgeKey::Transition(TransitionSource::new(tk.state.clone(),
-				                                                       tk.event.clone()));
-
-				self.dotgraph.edges.insert(key.clone(),
-				                           DotEdge {
-					                           key: key,
-					                           style: dot::Style::None,
-					                           label: format!("{}\n|{}|", tv.name.clone()
-						                           .unwrap_or(String::from("")),
-					                                          event2name.get(&tk.event)
-						                                          .unwrap_or(&""))
-				                           }
+		// generate the edges now & label them, dynamic transitions get one per target
+		for t in self.transitions.iter() {
+			let (tk, tv) = t;
+
+			for target in tv.targets.iter() {
+				let key = DotEdgeKey::Transition((tk.clone(), target.clone()));
+
+				g.edges.insert(key.clone(),
+				               DotEdge {
+					               key,
+					               source: DotNodeKey(None, tk.state.clone()),
+					               target: DotNodeKey(None, target.clone()),
+					               style: if tv.is_dynamic() { dot::Style::Dashed } else { dot::Style::None },
+					               color: None,
+					               label: format!("{}\n|{}|{}", tv.name.clone()
+						               .unwrap_or(String::from("")),
+					                              event2name.get(&tk.event)
+						                              .unwrap_or(&""),
+					                              // self transitions leaving the state are marked
+					                              if tk.state == *target && tv.kind == TransitionKind::External {
+						                              "\nexternal"
+					                              } else {
+						                              ""
+					                              })
+				               }
 				);
 			}
+		}
+
+		for t in self.statetransitions.iter() {
+			let (tk, tv) = t;
 
-			for t in self.statetransitions.iter() {
-				let (tk, tv) = t;
+			let key: DotEdgeKey<StateType, EventType> = DotEdgeKey::EntryExit((tk.0.clone(),
+			                                                                   tk.1.clone()));
+			// entry transitions lead from the shadow node into the state,
+			// exit transitions from the state into the shadow node
+			let (source, target) = match tk.1 {
+				EntryExit::EntryTransition => {
+					(DotNodeKey(Some(tk.1.clone()), tk.0.clone()), DotNodeKey(None, tk.0.clone()))
+				}
+				EntryExit::ExitTransition => {
+					(DotNodeKey(None, tk.0.clone()), DotNodeKey(Some(tk.1.clone()), tk.0.clone()))
+				}
+			};
 
-				let key: DotEdgeKey<StateType, EventType> = DotEdgeKey::EntryExit((tk.0.clone(),
-				                                                                   tk.1.clone()));
+			g.edges.insert(key.clone(),
+			               DotEdge {
+				               key,
+				               source,
+				               target,
+				               style: dot::Style::None,
+				               color: None,
+				               label: tv.1.clone().unwrap_or_default()
+			               });
+		}
 
-				self.dotgraph.edges.insert(key.clone(),
-				                           DotEdge {
-					                           key: key,
-					                           style: dot::Style::None,
-					                           label: format!("{}",
-					                                          tv.1.clone().unwrap_or(
-						                                          String::from("")))
-				                           });
+		// branches of choices lead out of the choice node, labelled with their guard
+		for (c, choice) in self.choices.iter() {
+			for (target, name) in choice.branches() {
+				let key = DotEdgeKey::Choice((c.clone(), target.clone()));
+				g.edges.insert(key.clone(),
+				               DotEdge {
+					               key,
+					               source: DotNodeKey(None, c.clone()),
+					               target: DotNodeKey(None, target.clone()),
+					               style: dot::Style::None,
+					               color: None,
+					               label: format!("[{}]", name.unwrap_or("else")),
+				               });
 			}
+		}
 
-			let render = move |mut mf, mut sout| {
-				match &mut mf {
-					&mut Some(ref mut f) => dot::render(self, f),
-					_ => dot::render(self, &mut sout) // as io::Write
-				}
-			};
+		// completion transitions are dotted, they do not wait for an event
+		for (s, c) in self.completions.iter() {
+			let key = DotEdgeKey::Completion(s.clone());
+			g.edges.insert(key.clone(),
+			               DotEdge {
+				               key,
+				               source: DotNodeKey(None, s.clone()),
+				               target: DotNodeKey(None, c.endstate.clone()),
+				               style: dot::Style::Dotted,
+				               color: None,
+				               label: c.name.clone().unwrap_or_default(),
+			               });
+		}
 
-			render(maybef, sout)
-		} else {
-			Err(fileattempt.err().unwrap()) // error
+		// annotate the edges with what the machine has done so far
+		let maxcount = g.edges.keys()
+			.map(|k| self.stats.count(k))
+			.max()
+			.unwrap_or(0);
+
+		for (k, e) in g.edges.iter_mut() {
+			if options.color_by_frequency {
+				let count = self.stats.count(k);
+				e.color = if count == 0 {
+					Some(DOT_UNTAKEN_COLOR)
+				} else {
+					let buckets = DOT_FREQUENCY_COLORS.len() as u64;
+					let bucket = ((count * buckets - 1) / maxcount) as usize;
+					Some(DOT_FREQUENCY_COLORS[bucket])
+				};
+				e.label = format!("{}\n{}x", e.label, count);
+			}
+			if let Some(pos) = self.stats.recent_position(k, options.recent_transitions) {
+				e.style = dot::Style::Bold;
+				e.color = Some(DOT_RECENT_COLOR);
+				e.label = format!("{}\n#{}", e.label, pos);
+			}
 		}
+
+		g
 	}
 
 	/// new transition
@@ -525,6 +977,111 @@ FSM<ExtendedState,
 		self.transitions.insert(from, to).is_none()
 	}
 
+	/// declares `choice` to be a choice pseudo-state. Transitions ending in it
+	/// continue within the same step to the target of the first branch whose guard
+	/// holds on the extended state after the transition, or to `otherwise`. The
+	/// machine never rests in a choice, the exit transition of the state left and
+	/// the entry transition of the target are run even if they are the same.
+	///
+	/// `returns` - TRUE if the choice has been inserted,
+	///             FALSE if a previous has been overwritten!
+	pub fn add_choice(&mut self, choice: StateType,
+	                  branches: Vec<ChoiceBranch<ExtendedState, StateType>>,
+	                  otherwise: StateType) -> bool {
+		self.choices.insert(choice, Choice {
+			branches,
+			otherwise,
+		}).is_none()
+	}
+
+	/// declares `history` to be a history pseudo-state of the group of states
+	/// `members`. Transitions ending in it resume the group where the machine has
+	/// left it, or enter it at `initial` if it has not been in it yet, like a
+	/// choice running the exit and entry transitions. Members may be history
+	/// pseudo-states themselves standing for nested groups, `kind` tells whether
+	/// those are resumed as well or entered at their initial state.
+	///
+	/// `returns` - TRUE if the history has been inserted, FALSE if a previous has
+	///             been overwritten or `initial` is no member, then the history
+	///             is not inserted!
+	pub fn add_history(&mut self, history: StateType, kind: HistoryKind,
+	                   members: Vec<StateType>, initial: StateType) -> bool {
+		if !members.contains(&initial) {
+			return false;
+		}
+		let inserted = self.histories.insert(history, History {
+			kind,
+			members,
+			initial,
+			last: None,
+		}).is_none();
+		let state = self.current_state.clone();
+		self.remember(&state);
+		inserted
+	}
+
+	/// updates the histories of all groups `state` is in
+	fn remember(&mut self, state: &StateType) {
+		let remembered = self.histories.iter()
+			.filter_map(|(h, history)| {
+				let member = self.member_containing(h, state, 0)?;
+				Some((h.clone(), match history.kind {
+					HistoryKind::Shallow => member,
+					HistoryKind::Deep => state.clone(),
+				}))
+			})
+			.collect::<Vec<_>>();
+		for (h, last) in remembered {
+			if let Some(history) = self.histories.get_mut(&h) {
+				history.last = Some(last);
+			}
+		}
+	}
+
+	/// `returns` - member of the group of `history` which is or contains `state`
+	fn member_containing(&self, history: &StateType, state: &StateType, depth: usize)
+	                     -> Option<StateType> {
+		// nested groups may form a cycle
+		if depth > self.histories.len() {
+			return None;
+		}
+		self.histories.get(history)?.members.iter()
+			.find(|m| *m == state || self.member_containing(m, state, depth + 1).is_some())
+			.cloned()
+	}
+
+	/// `returns` - state `history` resumes, shallow histories enter nested groups
+	///             at their initial state
+	fn resume(&self, history: &History<StateType>) -> StateType {
+		let mut state = match (history.kind, &history.last) {
+			(HistoryKind::Deep, Some(last)) => return last.clone(),
+			(HistoryKind::Shallow, Some(last)) => last.clone(),
+			(_, None) => history.initial.clone(),
+		};
+		for _ in 0..self.histories.len() {
+			match self.histories.get(&state) {
+				Some(nested) => state = nested.initial.clone(),
+				None => break,
+			}
+		}
+		state
+	}
+
+	/// new completion transition taken without an event right after `state` has
+	/// been entered, unless its guard does not hold. Completions chain, a state
+	/// entered by a completion may complete itself.
+	///
+	/// `returns` - TRUE if transition has been inserted,
+	///             FALSE if a previous has been overwritten!
+	pub fn add_completion(&mut self, state: StateType,
+	                      completion: CompletionTransition<ExtendedState,
+		                      StateType,
+		                      EventType,
+		                      TransitionFnArguments,
+		                      ErrorType>) -> bool {
+		self.completions.insert(state, completion).is_none()
+	}
+
 	/// new enter/exit transition per state
 	/// executed _after_ the transition right before
 	/// the state is entered. If the machine remains in the same state
@@ -542,7 +1099,45 @@ FSM<ExtendedState,
 	                            name: Option<&str>) -> bool
 	{
 		self.statetransitions.insert(case,
-		                             (trans, name.map(|s| String::from(s)))).is_none()
+		                             (EntryExitFn::Plain(trans), name.map(String::from))).is_none()
+	}
+
+	/// new enter/exit transition per state like `add_enter_transition` getting
+	/// the event, the states and the argument of the transition it is triggered
+	/// by. Since the argument is handed to the transition before the state is
+	/// entered, the machine keeps a copy of every argument from now on.
+	///
+	/// `returns` - TRUE if transition has been inserted, FALSE if a
+	///             previous has been overwritten!
+	pub fn add_enter_transition_with_context(&mut self, case: (StateType, EntryExit),
+	                                         trans:
+	                                         Box<ContextEntryExitTransitionFn<ExtendedState,
+		                                         EventType,
+		                                         StateType,
+		                                         TransitionFnArguments,
+		                                         ErrorType>>,
+	                                         name: Option<&str>) -> bool
+		where TransitionFnArguments: Clone
+	{
+		self.keep_arg = Some(TransitionFnArguments::clone);
+		self.statetransitions.insert(case,
+		                             (EntryExitFn::Context(trans), name.map(String::from))).is_none()
+	}
+
+	/// attaches a recorder observing all events added from the outside and the
+	/// state the machine is left in after each processing of the event queue.
+	/// Events queued by transitions themselves are _not_ recorded since they
+	/// are reproduced on replay.
+	///
+	/// `returns` - previously attached recorder if any
+	pub fn set_recorder(&mut self,
+	                    recorder: Option<Box<dyn RecordsFSM<EventType,
+		                    StateType,
+		                    TransitionFnArguments>>>)
+	                    -> Option<Box<dyn RecordsFSM<EventType,
+		                    StateType,
+		                    TransitionFnArguments>>> {
+		std::mem::replace(&mut self.recorder, recorder)
 	}
 
 	pub fn name(&self) -> &String {
@@ -551,7 +1146,7 @@ FSM<ExtendedState,
 
 	/// gives a read only peek into the extended state from the outside of transitions.
 	/// Must be given up before running machine of course
-	pub fn extended_state(&self) -> Ref<Box<ExtendedState>> {
+	pub fn extended_state(&self) -> Ref<'_, Box<ExtendedState>> {
 		self.extended_state.borrow()
 	}
 
@@ -560,9 +1155,20 @@ FSM<ExtendedState,
 		self.current_state.clone()
 	}
 
-	/// `returns` - TRUE if machine has outstanding events queued to process
+	/// `returns` - TRUE if machine has outstanding events queued or posted to process
 	pub fn events_pending(&self) -> bool {
-		self.event_queue.len() > 0
+		!self.event_queue.is_empty() || self.inbox.pending() > 0
+	}
+
+	/// events queued to be processed in order, without their arguments. Events
+	/// posted through handles are not queued before the next processing.
+	pub fn pending_events(&self) -> Vec<EventType> {
+		self.event_queue.iter().map(|e| e.0.clone()).collect()
+	}
+
+	/// `returns` - handle other threads can post events into the machine with
+	pub fn handle(&self) -> EventHandle<EventType, TransitionFnArguments> {
+		self.inbox.clone()
 	}
 }
 
@@ -581,24 +1187,41 @@ TransitionSource<StateType, EventType> {
 	pub fn new(state: StateType,
 	           event: EventType) -> TransitionSource<StateType, EventType> {
 		TransitionSource {
-			state: state,
-			event: event,
+			state,
+			event,
 		}
 	}
 }
 
 type EntryExitKey<StateType> = (StateType, EntryExit);
 
+/// transition upon an event together with one state it can end in
+type TransitionEdge<StateType, EventType> = (TransitionSource<StateType, EventType>, StateType);
+
+/// function of a transition with a fixed or a dynamically chosen end state
+enum TargetFn<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	Fixed(Box<TransitionFn<ExtendedState,
+		EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>>),
+	Dynamic(Box<DynamicTransitionFn<ExtendedState,
+		EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>>),
+}
+
 /// implements the target of a transition upon an event
 pub struct TransitionTarget<ExtendedState, StateType, EventType,
                             TransitionFnArguments, ErrorType> {
+	/// end state of fixed transitions, first declared target of dynamic ones
 	endstate: StateType,
-	transfn: Box<TransitionFn<ExtendedState,
-		EventType,
-		StateType,
-		TransitionFnArguments,
-		ErrorType>>,
+	/// all states the transition can end in
+	targets: Vec<StateType>,
+	transfn: TargetFn<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
 	name: Option<String>,
+	kind: TransitionKind,
 }
 
 impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
@@ -607,7 +1230,7 @@ TransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, Err
 	/// create a transition target
 	///   * `endstate` - state resulting after correct transition
 	///   * `transfn`  - transition as a boxed function taking in extended state,
-	/// 				 event and possible arguments
+	///     event and possible arguments
 	///   * `name`     - optional transition name, helpful if `transfn` is a closure
 	pub fn new(endstate: StateType,
 	           transfn: Box<TransitionFn<ExtendedState,
@@ -617,26 +1240,193 @@ TransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, Err
 		           ErrorType>>,
 	           name: Option<&str>)
 	           -> TransitionTarget
-	           <ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	           <ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+		where StateType: Clone
+	{
+		TransitionTarget {
+			endstate: endstate.clone(),
+			targets: vec![endstate],
+			transfn: TargetFn::Fixed(transfn),
+			name: name.map(String::from),
+			kind: TransitionKind::default(),
+		}
+	}
+
+	/// create a transition target choosing the end state when the transition runs.
+	/// The exit transition of the state left runs before the transition function
+	/// like for fixed targets, unless the state is one of the targets and the
+	/// transition internal. Whether it is left is known only after the transition
+	/// function ran then, so its exit transition runs after it.
+	///   * `first`, `rest` - all states the transition can end in, drawn as dashed
+	///     edges. Choosing any other state fails with `Errors::InvalidTarget`
+	///   * `transfn` - transition returning the end state with the events to queue
+	///   * `name`    - optional transition name
+	pub fn dynamic(first: StateType,
+	               rest: Vec<StateType>,
+	               transfn: Box<DynamicTransitionFn<ExtendedState,
+		               EventType,
+		               StateType,
+		               TransitionFnArguments,
+		               ErrorType>>,
+	               name: Option<&str>)
+	               -> TransitionTarget
+	               <ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+		where StateType: Clone
+	{
+		let mut targets = vec![first.clone()];
+		targets.extend(rest);
 		TransitionTarget {
-			endstate: endstate,
-			transfn: transfn,
-			name: name.map(|s| String::from(s))
+			endstate: first,
+			targets,
+			transfn: TargetFn::Dynamic(transfn),
+			name: name.map(String::from),
+			kind: TransitionKind::default(),
 		}
 	}
+
+	/// sets whether the transition leaves the state it starts from if it ends in
+	/// it again, `TransitionKind::Internal` unless set
+	pub fn with_kind(mut self, kind: TransitionKind)
+	                 -> TransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		self.kind = kind;
+		self
+	}
+
+	/// `returns` - TRUE if the end state is chosen by the transition function
+	pub(crate) fn is_dynamic(&self) -> bool {
+		matches!(self.transfn, TargetFn::Dynamic(_))
+	}
 }
 
-/// map of from state/event to end state/transition
-type TransitionTable<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> =
-HashMap<// from
-	TransitionSource<StateType, EventType>,
-	TransitionTarget<ExtendedState,
-		StateType,
+/// choice pseudo-state together with the target of one of its branches
+type ChoiceKey<StateType> = (StateType, StateType);
+
+/// branch of a choice pseudo-state taken if its guard holds
+pub struct ChoiceBranch<ExtendedState, StateType> {
+	target: StateType,
+	guard: Box<Guard<ExtendedState>>,
+	name: Option<String>,
+}
+
+impl<ExtendedState, StateType> ChoiceBranch<ExtendedState, StateType> {
+	/// create a choice branch
+	///   * `target` - state the machine continues to
+	///   * `guard`  - predicate over the extended state the branch is taken on
+	///   * `name`   - optional guard name, drawn on the branch
+	pub fn new(target: StateType, guard: Box<Guard<ExtendedState>>, name: Option<&str>)
+	           -> ChoiceBranch<ExtendedState, StateType> {
+		ChoiceBranch {
+			target,
+			guard,
+			name: name.map(String::from),
+		}
+	}
+}
+
+/// branches of a choice tried in order and the target if no guard holds
+struct Choice<ExtendedState, StateType> {
+	branches: Vec<ChoiceBranch<ExtendedState, StateType>>,
+	otherwise: StateType,
+}
+
+impl<ExtendedState, StateType> Choice<ExtendedState, StateType> {
+	/// targets of all branches with their guard names, the otherwise branch last
+	/// without name
+	fn branches(&self) -> Vec<(&StateType, Option<&str>)> {
+		self.branches.iter()
+			.map(|b| (&b.target, Some(b.name.as_deref().unwrap_or(""))))
+			.chain(Some((&self.otherwise, None)))
+			.collect()
+	}
+
+	/// `returns` - target of the first branch whose guard holds on `x`
+	fn choose(&self, x: &ExtendedState) -> &StateType {
+		self.branches.iter()
+			.find(|b| (b.guard)(x))
+			.map_or(&self.otherwise, |b| &b.target)
+	}
+}
+
+/// transition taken without an event once a state has been entered
+pub struct CompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	endstate: StateType,
+	transfn: Box<EntryExitTransitionFn<ExtendedState,
 		EventType,
+		StateType,
 		TransitionFnArguments,
-		ErrorType>>;
+		ErrorType>>,
+	guard: Option<Box<Guard<ExtendedState>>>,
+	guard_name: Option<String>,
+	name: Option<String>,
+}
 
-/// map for state entry/exit transitions
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+CompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	/// create a completion transition, always leaving the state it completes
+	///   * `endstate` - state resulting after correct transition
+	///   * `transfn`  - transition taking in the extended state only
+	///   * `name`     - optional transition name
+	pub fn new(endstate: StateType,
+	           transfn: Box<EntryExitTransitionFn<ExtendedState,
+		           EventType,
+		           StateType,
+		           TransitionFnArguments,
+		           ErrorType>>,
+	           name: Option<&str>)
+	           -> CompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		CompletionTransition {
+			endstate,
+			transfn,
+			guard: None,
+			guard_name: None,
+			name: name.map(String::from),
+		}
+	}
+
+	/// takes the transition only if `guard` holds on the extended state after
+	/// entry, `name` is the optional guard name
+	pub fn with_guard(mut self, guard: Box<Guard<ExtendedState>>, name: Option<&str>)
+	                  -> CompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		self.guard = Some(guard);
+		self.guard_name = name.map(String::from);
+		self
+	}
+}
+
+/// group of states a history pseudo-state resumes
+struct History<StateType> {
+	kind: HistoryKind,
+	members: Vec<StateType>,
+	initial: StateType,
+	/// member the machine has been in last for shallow histories, state for deep
+	last: Option<StateType>,
+}
+
+/// map of from state/event to end state/transition
+type TransitionTable<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> =
+HashMap<// from
+	TransitionSource<StateType, EventType>,
+	TransitionTarget<ExtendedState,
+		StateType,
+		EventType,
+		TransitionFnArguments,
+		ErrorType>>;
+
+/// function of an entry/exit transition with or without its context
+enum EntryExitFn<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	Plain(Box<EntryExitTransitionFn<ExtendedState,
+		EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>>),
+	Context(Box<ContextEntryExitTransitionFn<ExtendedState,
+		EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>>),
+}
+
+/// map for state entry/exit transitions
 type EntryExitTransitionTable<ExtendedState,
                               StateType,
                               EventType,
@@ -645,11 +1435,7 @@ type EntryExitTransitionTable<ExtendedState,
 HashMap<// from
 	EntryExitKey<StateType>,
 	// transition
-	(Box<EntryExitTransitionFn<ExtendedState,
-		EventType,
-		StateType,
-		TransitionFnArguments,
-		ErrorType>>,
+	(EntryExitFn<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
 	 Option<String>)>;
 
 impl<ExtendedState, EventType, StateType,
@@ -669,6 +1455,12 @@ for FSM<ExtendedState, StateType, EventType,
 
 		debug!(self.log, "FSM {} adding {} events", self.name, el);
 
+		if let Some(ref mut recorder) = self.recorder {
+			for e in events.iter() {
+				recorder.record_event(&e.0, e.1.as_deref());
+			}
+		}
+
 		// move the queue into the closure and add events
 		events.drain(..).map(move |e| {
 			self.event_queue.push_back(e);
@@ -678,142 +1470,316 @@ for FSM<ExtendedState, StateType, EventType,
 	}
 
 	fn process_event_queue(&mut self) -> Result<u32, Errors<EventType, StateType, ErrorType>> {
+		// posted events are queued as if they had been added from the outside
+		let mut posted = self.inbox.take();
+		if !posted.is_empty() {
+			self.add_events(&mut posted)?;
+		}
+
 		// need to recopy since we will be adding new events on transition possibly
 		// so current events need to be frozen
-		let mut evs = self.event_queue.drain(..).collect::<Vec<_>>();
+		let evs = self.event_queue.drain(..).collect::<Vec<_>>();
 		let nrev = evs.len() as u32;
 
-		let mut lr: Vec<Errors<EventType, StateType, ErrorType>> =
-			evs.drain(..).map(|e| {
-				let state = self.current_state.clone();
-				let event = e.0.clone();
-				let trans = self.transitions.get(&TransitionSource::new(state.clone(),
-				                                                        event.clone()));
-				let ref mut q = self.event_queue;
-				let name = &self.name;
-				debug!(self.log, "FSM {} processing event {:?}/{:?}", name, event, state);
-
-				// play the entry, exit transition draining the event queues if necessary
-				fn entryexit<ExtendedState, EventType, StateType,
-				             TransitionFnArguments, ErrorType>(
-					log: &Logger,
-					extstate: RefMut<Box<ExtendedState>>,
-					name: &str,
-					s: StateType,
-					dir: EntryExit,
-					q: &mut EventQueue<EventType, TransitionFnArguments>,
-					trans: &EntryExitTransitionTable<ExtendedState,
-						StateType, EventType,
-						TransitionFnArguments, ErrorType>)
-					-> Errors<EventType, StateType, ErrorType>
-					where StateType: Clone + PartialEq + Eq + Hash + Debug,
-					      EventType: Clone + PartialEq + Eq + Hash + Debug,
-					      ErrorType: Debug
-				{
-					match trans.get(&(s.clone(), dir)) {
-						None => Errors::OK,
-						Some(ref tuple) => {
-							let ref func = tuple.0;
-							let ref tname = tuple.1;
-							debug!(log, "FSM {} exit/entry state transition for {:?} {:?}",
-							name, s, tname);
-							match func(extstate) {
-								Err(v) => v,
-								Ok(ref mut v) => {
-									match v {
-										&mut Some(ref mut eventset) => {
-											eventset.drain(..).map(
-												|e|
-													q.push_back(e)
-											).last();
-											Errors::OK
-										}
-										&mut None => Errors::OK,
-									}
-								}
-							}
+		// stop on the first error
+		let mut res = Errors::OK;
+		for e in evs {
+			res = self.process_event(e);
+			if !matches!(res, Errors::OK) {
+				break;
+			}
+		}
+
+		if let Some(ref mut recorder) = self.recorder {
+			let result = match res {
+				Errors::OK => Ok(nrev),
+				ref x => Err(format!("{:?}", x)),
+			};
+			recorder.record_processed(&self.current_state, result);
+		}
+
+		// check whether we got any errors on transitions
+		match res {
+			Errors::OK => Ok(nrev),
+			x => {
+				debug!(self.log, "FSM {} filter on transition failures yields {:?}",
+				self.name, &x);
+				Err(x)
+			}
+		}
+	}
+}
+
+/// processing of a single event split into its steps
+impl<ExtendedState, EventType, StateType,
+     TransitionFnArguments, ErrorType>
+FSM<ExtendedState, StateType, EventType,
+	TransitionFnArguments, ErrorType>
+	where StateType: Clone + PartialEq + Eq + Hash + Debug + Sized,
+	      EventType: Clone + PartialEq + Eq + Hash + Debug + Sized,
+	      ErrorType: Debug
+{
+	/// play the entry, exit transition queueing the events it returns
+	fn entryexit(&mut self, s: StateType, dir: EntryExit,
+	             context: &EntryExitContext<StateType, EventType, TransitionFnArguments>)
+	             -> Errors<EventType, StateType, ErrorType> {
+		let key = (s.clone(), dir);
+		match self.statetransitions.get(&key) {
+			None => Errors::OK,
+			Some(tuple) => {
+				let func = &tuple.0;
+				let tname = &tuple.1;
+				debug!(self.log, "FSM {} exit/entry state transition for {:?} {:?}",
+				self.name, s, tname);
+				self.stats.entryexit_taken(&key);
+				let result = match *func {
+					EntryExitFn::Plain(ref f) => f(self.extended_state.borrow_mut()),
+					EntryExitFn::Context(ref f) => f(self.extended_state.borrow_mut(), context),
+				};
+				match result {
+					Err(v) => v,
+					Ok(v) => {
+						if let Some(eventset) = v {
+							self.event_queue.extend(eventset);
 						}
+						Errors::OK
 					}
 				}
+			}
+		}
+	}
 
-				match trans {
-					Some(itrans) => {
-						let endstate = itrans.endstate.clone();
-						let transfn = &itrans.transfn;
-
-						let mut res = Errors::OK;
-
-						res = if state == endstate.clone() {
-							res
-						} else {
-							// run exit for state
-							let extstate = self.extended_state.borrow_mut();
-							entryexit(&self.log,
-							          extstate, name, state.clone(),
-							          EntryExit::ExitTransition, q, &self.statetransitions)
-						};
+	/// `returns` - TRUE if the transition upon `source` ending in `endstate` does
+	///             not leave the state it starts from
+	fn remains(&self, source: &TransitionSource<StateType, EventType>, endstate: &StateType) -> bool {
+		source.state == *endstate &&
+			self.transitions.get(source).is_none_or(|t| t.kind == TransitionKind::Internal)
+	}
 
-						// only continue if exit was ok
-						res = match res {
-							Errors::OK => {
-								let extstate = self.extended_state.borrow_mut();
-								// match ref mutably the resulting event set of the transition and
-								// drain it into our queue back
-								match transfn(extstate, e.0, e.1) {
-									Err(v) => v,
-									Ok(v) => {
-										match v {
-											None => {}
-											Some(eventset) => {
-												q.extend(eventset)
-											}
-										}
-										debug!(self.log, "FSM {} moving machine to {:?}",
-										name, endstate);
-										self.current_state = endstate.clone();
-										Errors::OK
-									}
-								}
-							}
-							r => r,
-						};
+	/// runs the exit transition of the state `source` starts from unless the
+	/// machine remains in it
+	pub(crate) fn leave(&mut self, source: &TransitionSource<StateType, EventType>, endstate: &StateType,
+	                    arg: Option<&TransitionFnArguments>)
+	                    -> Errors<EventType, StateType, ErrorType> {
+		if self.remains(source, endstate) {
+			Errors::OK
+		} else {
+			let context = EntryExitContext {
+				event: Some(&source.event),
+				from: &source.state,
+				to: endstate,
+				arg,
+			};
+			self.entryexit(source.state.clone(), EntryExit::ExitTransition, &context)
+		}
+	}
 
-						// see whether we have entry into the next one
-						match res {
-							Errors::OK => {
-								if state == endstate.clone() {
-									res
-								} else {
-									let extstate = self.extended_state.borrow_mut();
-									entryexit(&self.log,
-									          extstate, name, endstate.clone(),
-									          EntryExit::EntryTransition, q,
-									          &self.statetransitions)
-								}
-							}
-							r => r,
-						}
-					}
-					None =>
-						Errors::NoTransition(event, state),
+	/// `returns` - copy of `arg` for entry transitions if any of them wants it
+	pub(crate) fn keep(&self, arg: &OptionalFnArg<TransitionFnArguments>) -> Option<TransitionFnArguments> {
+		match (self.keep_arg, arg.as_deref()) {
+			(Some(keep), Some(arg)) => Some(keep(arg)),
+			_ => None,
+		}
+	}
+
+	/// takes the result of the transition on `event` in `state`, queues the events
+	/// it returned, moves the machine to `endstate` and runs its entry transition
+	/// with the argument `kept` for it
+	pub(crate) fn complete(&mut self, state: StateType, event: EventType, endstate: StateType,
+	                       kept: Option<TransitionFnArguments>,
+	                       result: TransitionResult<EventType,
+		                       StateType,
+		                       TransitionFnArguments,
+		                       ErrorType>)
+	                       -> Errors<EventType, StateType, ErrorType> {
+		match result {
+			Err(v) => v,
+			Ok(v) => {
+				if let Some(eventset) = v {
+					self.event_queue.extend(eventset);
 				}
-				// check for any errors in the whole transitions of the queue
-			}).filter(|e| {
-				match *e {
-					Errors::OK => false,
-					_ => true
+				let source = TransitionSource::new(state, event);
+				let remains = self.remains(&source, &endstate);
+				self.stats.transition_taken(&(source.clone(), endstate.clone()));
+
+				// choices and histories are passed through right away
+				let through_pseudo = self.choices.contains_key(&endstate) ||
+					self.histories.contains_key(&endstate);
+				let endstate = match self.arrive(endstate) {
+					Some(s) => s,
+					None => return Errors::TransitionFailure,
+				};
+
+				// see whether we have entry into the next one
+				if remains && !through_pseudo {
+					return Errors::OK;
 				}
-			}).take(1).collect::<Vec<_>>(); // try to get first error out if any
+				let context = EntryExitContext {
+					event: Some(&source.event),
+					from: &source.state,
+					to: &endstate,
+					arg: kept.as_ref(),
+				};
+				match self.entryexit(endstate.clone(), EntryExit::EntryTransition, &context) {
+					Errors::OK => self.run_completions(endstate.clone()),
+					r => r,
+				}
+			}
+		}
+	}
 
-		// check whether we got any errors on transitions
-		match lr.pop() {
-			Some(x) => {
-				debug!(self.log, "FSM {} filter on transition failures yields {:?}",
-				self.name, &x);
-				Err(x)
+	/// moves the machine to `endstate` passing through choices and histories
+	///
+	/// `returns` - the state the machine is in now, None if choices lead into a cycle
+	fn arrive(&mut self, endstate: StateType) -> Option<StateType> {
+		let endstate = self.choose(endstate)?;
+		debug!(self.log, "FSM {} moving machine to {:?}", self.name, endstate);
+		self.current_state = endstate.clone();
+		self.remember(&endstate);
+		Some(endstate)
+	}
+
+	/// takes the completion transitions starting at `state` which has just been
+	/// entered, as long as their guards hold
+	fn run_completions(&mut self, mut state: StateType) -> Errors<EventType, StateType, ErrorType> {
+		for _ in 0..MAX_COMPLETIONS {
+			let endstate = match self.completions.get(&state) {
+				Some(c) if c.guard.as_ref().is_none_or(|g| g(&self.extended_state.borrow())) => {
+					c.endstate.clone()
+				}
+				_ => return Errors::OK,
+			};
+			debug!(self.log, "FSM {} completing {:?}", self.name, state);
+
+			let context = EntryExitContext { event: None, from: &state, to: &endstate, arg: None };
+			match self.entryexit(state.clone(), EntryExit::ExitTransition, &context) {
+				Errors::OK => {}
+				r => return r,
+			}
+			let result = match self.completions.get(&state) {
+				Some(c) => (c.transfn)(self.extended_state.borrow_mut()),
+				// exit transitions cannot change the completions
+				None => unreachable!(),
+			};
+			match result {
+				Err(v) => return v,
+				Ok(Some(eventset)) => self.event_queue.extend(eventset),
+				Ok(None) => {}
+			}
+			self.stats.completion_taken(&state);
+
+			let from = state;
+			state = match self.arrive(endstate) {
+				Some(s) => s,
+				None => return Errors::TransitionFailure,
+			};
+			let context = EntryExitContext { event: None, from: &from, to: &state, arg: None };
+			match self.entryexit(state.clone(), EntryExit::EntryTransition, &context) {
+				Errors::OK => {}
+				r => return r,
+			}
+		}
+		warn!(self.log, "FSM {} completions still firing in {:?} after {} steps",
+		      self.name, state, MAX_COMPLETIONS);
+		Errors::TransitionFailure
+	}
+
+	/// follows choices and histories starting at `state` until a real state is
+	/// reached
+	///
+	/// `returns` - the real state, None if choices lead into a cycle
+	fn choose(&mut self, mut state: StateType) -> Option<StateType> {
+		for _ in 0..=self.choices.len() + self.histories.len() {
+			let next = if let Some(choice) = self.choices.get(&state) {
+				let next = choice.choose(&self.extended_state.borrow()).clone();
+				self.stats.choice_taken(&(state.clone(), next.clone()));
+				next
+			} else if let Some(history) = self.histories.get(&state) {
+				self.resume(history)
+			} else {
+				return Some(state);
+			};
+			debug!(self.log, "FSM {} {:?} continues to {:?}", self.name, state, next);
+			state = next;
+		}
+		None
+	}
+
+	/// runs exit transition, transition and entry transition for a single event
+	pub(crate) fn process_event(&mut self, e: (EventType, OptionalFnArg<TransitionFnArguments>))
+	                            -> Errors<EventType, StateType, ErrorType> {
+		let state = self.current_state.clone();
+		let source = TransitionSource::new(state.clone(), e.0.clone());
+		debug!(self.log, "FSM {} processing event {:?}/{:?}", self.name, e.0, state);
+
+		let endstate = match self.transitions.get(&source) {
+			Some(itrans) => match itrans.transfn {
+				TargetFn::Fixed(_) => itrans.endstate.clone(),
+				TargetFn::Dynamic(_) => return self.process_dynamic(source, e.1),
+			},
+			None => return Errors::NoTransition(e.0, state),
+		};
+
+		// only continue if exit was ok
+		match self.leave(&source, &endstate, e.1.as_deref()) {
+			Errors::OK => {}
+			r => return r,
+		}
+
+		let kept = self.keep(&e.1);
+		let result = match self.transitions.get(&source) {
+			Some(&TransitionTarget { transfn: TargetFn::Fixed(ref transfn), .. }) => {
+				transfn(self.extended_state.borrow_mut(), e.0.clone(), e.1)
+			}
+			// exit transitions cannot change the transition table
+			_ => unreachable!(),
+		};
+		self.complete(state, e.0, endstate, kept, result)
+	}
+
+	/// runs the exit transition, a dynamic transition and the entry transition like
+	/// fixed transitions do. Only if the dynamic transition may remain in its state
+	/// the exit transition runs after it once the end state is known.
+	fn process_dynamic(&mut self, source: TransitionSource<StateType, EventType>,
+	                   arg: OptionalFnArg<TransitionFnArguments>)
+	                   -> Errors<EventType, StateType, ErrorType> {
+		let (state, event) = (source.state.clone(), source.event.clone());
+		let kept = self.keep(&arg);
+		let first = match self.transitions.get(&source) {
+			Some(t) if !self.remains(&source, &state) || !t.targets.contains(&state) => {
+				Some(t.endstate.clone())
+			}
+			_ => None,
+		};
+
+		// the exit sees the first target as the end state is not known yet
+		if let Some(ref first) = first {
+			match self.leave(&source, first, arg.as_deref()) {
+				Errors::OK => {}
+				r => return r,
 			}
-			_ => Ok(nrev)
 		}
+
+		let (endstate, events) = match self.transitions.get(&source) {
+			Some(&TransitionTarget { transfn: TargetFn::Dynamic(ref transfn), ref targets, .. }) => {
+				match transfn(self.extended_state.borrow_mut(), event.clone(), arg) {
+					Err(v) => return v,
+					Ok((endstate, _)) if !targets.contains(&endstate) => {
+						return Errors::InvalidTarget(event, state, endstate);
+					}
+					Ok(r) => r,
+				}
+			}
+			_ => unreachable!(),
+		};
+
+		if first.is_none() {
+			match self.leave(&source, &endstate, kept.as_ref()) {
+				Errors::OK => {}
+				r => return r,
+			}
+		}
+		self.complete(state, event, endstate, kept, Ok(events))
 	}
 }
 
@@ -834,17 +1800,40 @@ mod tests {
 	use slog::*;
 	use self::slog_atomic::*;
 
-	use super::{FSM, Errors, RunsFSM, EntryExit, TransitionTarget, TransitionSource};
+	use super::{FSM, Errors, RunsFSM, EntryExit, TransitionTarget, TransitionSource, DotOptions,
+	            ChoiceBranch, TransitionKind, CompletionTransition, EntryExitContext, HistoryKind};
+	use super::replay::{Recorder, RecordEntry, replay};
+	use super::load::{Registry, MachineDescription, LoadError, load};
+	use super::codegen::{CodegenOptions, generate};
+	use super::explore::Explorer;
+	use super::coverage::Coverage;
+	use super::scenario::Scenario;
+	use super::send::{SendFSM, SendTransitionTarget};
+	use std::sync::mpsc;
+	use super::runtime::{Runtime, RuntimeError, RunErrors, ChildExit};
+	use super::supervisor::{Supervisor, RestartPolicy, Escalation};
+	#[cfg(feature = "async")]
+	use super::driver::AsyncFSM;
+	#[cfg(feature = "async")]
+	use futures::{executor, FutureExt};
+	#[cfg(feature = "async")]
+	use futures::channel::{mpsc as async_mpsc, oneshot};
+	#[cfg(feature = "proptest")]
+	use super::property::{SequenceCheck, sample_events, event_sequences};
+	#[cfg(feature = "proptest")]
+	use proptest::test_runner::{TestRunner, TestError};
 	use std::borrow::Borrow;
 	use std;
 
-	#[derive(Debug, Clone)]
+	#[derive(Debug, Clone, PartialEq, Eq)]
+	#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 	enum StillCoinType {
 		Good,
 		Bad,
 	}
 
-	#[derive(Debug, Clone)]
+	#[derive(Debug, Clone, PartialEq, Eq)]
+	#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 	enum StillArguments {
 		Coin(StillCoinType),
 	}
@@ -852,6 +1841,7 @@ mod tests {
 	custom_derive! {
 		#[derive(IterVariants(StateVariants), IterVariantNames(StateNames),
 			Debug, Clone, Hash, Eq, PartialEq)]
+		#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 		enum StillStates {
 			ClosedWaitForMoney,
 			CheckingMoney,
@@ -862,6 +1852,7 @@ mod tests {
 	custom_derive! {
 		#[derive(IterVariants(EventVariants), IterVariantNames(EventNames),
 			Debug, Clone, Hash, Eq, PartialEq)]
+		#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 		enum StillEvents {
 			GotCoin,
 			// needs coin type
@@ -876,6 +1867,7 @@ mod tests {
 		CoinArgumentMissing,
 	}
 
+	#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 	struct StillExtState {
 		coincounter: u32,
 		opened: u32,
@@ -917,15 +1909,15 @@ mod tests {
 					                          StillErrors::CoinArgumentMissing))
 				}
 				Some(arg) => {
-					match (*arg).borrow() {
-						&StillArguments::Coin(ref t) => {
-							match t {
-								&StillCoinType::Good => {
+					match *(*arg).borrow() {
+						StillArguments::Coin(ref t) => {
+							match *t {
+								StillCoinType::Good => {
 									Ok(Some(vec![(StillEvents::AcceptMoney, None)]
 										.into_iter()
 										.collect()))
 								}
-								&StillCoinType::Bad => {
+								StillCoinType::Bad => {
 									Ok(Some(vec![(StillEvents::RejectMoney, None)]
 										.into_iter()
 										.collect()))
@@ -1002,6 +1994,7 @@ mod tests {
 	}
 
 	#[test]
+	#[allow(non_fmt_panics, clippy::unused_unit, clippy::nonminimal_bool)]
 	fn coin_machine_test() {
 		let mut still_fsm = build_fsm();
 		// timeout should give no transition error
@@ -1049,17 +2042,76 @@ mod tests {
 		assert!(es.borrow().closed == 1);
 	}
 
-	fn zipit<ET>(i1: Box<Iterator<Item=ET>>,
-	             i2: Box<Iterator<Item=&'static str>>)
+	#[test]
+	fn coin_machine_replay() {
+		let goodcoin = Box::new(StillArguments::Coin(StillCoinType::Good));
+		let badcoin = Box::new(StillArguments::Coin(StillCoinType::Bad));
+
+		let recorder = Recorder::new();
+		let mut still_fsm = build_fsm();
+		still_fsm.set_recorder(Some(Box::new(recorder.clone())));
+
+		for coin in [badcoin, goodcoin] {
+			still_fsm.add_events(&mut vec![(StillEvents::GotCoin, Some(coin))]).unwrap();
+			while still_fsm.events_pending() {
+				assert!(still_fsm.process_event_queue().is_ok());
+			}
+		}
+		still_fsm.add_events(&mut vec![(StillEvents::Timeout, None)]).unwrap();
+		assert!(still_fsm.process_event_queue().is_ok());
+
+		let recording = recorder.recording();
+		// only the externally added events are recorded
+		assert_eq!(recording.entries.iter()
+			           .filter(|e| matches!(**e, RecordEntry::Event(_, _)))
+			           .count(), 3);
+		assert_eq!(recording.states().last(), Some(&&StillStates::ClosedWaitForMoney));
+
+		#[cfg(feature = "serde")]
+		let recording = {
+			let json = serde_json::to_string(&recording).expect("cannot serialize");
+			serde_json::from_str(&json).expect("cannot deserialize")
+		};
+
+		let mut replayed_fsm = build_fsm();
+		assert_eq!(replay(&mut replayed_fsm, &recording), Ok(recording.states().len() as u32));
+		assert!(replayed_fsm.extended_state().coincounter == 1);
+
+		// the same state reached with a different result diverges
+		let mut changed = recording.clone();
+		changed.entries[1] = RecordEntry::Processed(StillStates::CheckingMoney, Ok(2));
+		let divergence = replay(&mut build_fsm(), &changed).unwrap_err();
+		assert_eq!((divergence.step, divergence.expected, divergence.found),
+		           (1, StillStates::CheckingMoney, StillStates::CheckingMoney));
+		assert_eq!((divergence.expected_result, divergence.found_result), (Ok(2), Ok(1)));
+
+		// a timeout in front fails and drops the coin
+		let mut changed = recording;
+		changed.entries.insert(0, RecordEntry::Event(StillEvents::Timeout, None));
+		let divergence = replay(&mut build_fsm(), &changed).unwrap_err();
+		assert_eq!((divergence.step, divergence.expected, divergence.found),
+		           (2, StillStates::CheckingMoney, StillStates::ClosedWaitForMoney));
+		assert_eq!((divergence.expected_result, divergence.found_result),
+		           (Ok(1), Err(String::from("NoTransition(Timeout, ClosedWaitForMoney)"))));
+	}
+
+	fn zipit<ET>(i1: Box<dyn Iterator<Item=ET>>,
+	             i2: Box<dyn Iterator<Item=&'static str>>)
 	             -> HashMap<ET, &'static str>
 		where ET: Sized + Eq + Hash
 	{
 		i1.zip(i2).collect::<HashMap<_, _>>()
 	}
 
+	/// names of the still states and events
+	fn still_names() -> (HashMap<StillStates, &'static str>, HashMap<StillEvents, &'static str>) {
+		(zipit(Box::new(StillStates::iter_variants()), Box::new(StillStates::iter_variant_names())),
+		 zipit(Box::new(StillEvents::iter_variants()), Box::new(StillEvents::iter_variant_names())))
+	}
+
 	#[test]
 	fn coin_machine_dot() {
-		let mut still_fsm = build_fsm();
+		let still_fsm = build_fsm();
 
 		still_fsm.dotfile(None,
 		                  &zipit(Box::new(StillStates::iter_variants()),
@@ -1073,5 +2125,1398 @@ mod tests {
 		                  &zipit(Box::new(StillEvents::iter_variants()),
 		                         Box::new(StillEvents::iter_variant_names())))
 			.expect("cannot dotfile");
+
+		let mut out = Vec::new();
+		still_fsm.render_dot(&mut out,
+		                     &zipit(Box::new(StillStates::iter_variants()),
+		                            Box::new(StillStates::iter_variant_names())),
+		                     &zipit(Box::new(StillEvents::iter_variants()),
+		                            Box::new(StillEvents::iter_variant_names())),
+		                     &DotOptions::default())
+			.expect("cannot render dot");
+		let dot = String::from_utf8(out).expect("dot is not utf8");
+		assert!(dot.starts_with("digraph "));
+		assert!(dot.contains("[label=\"ClosedWaitForMoney\"][shape=\"diamond\"]"));
+		assert!(dot.contains("[label=\"Enter\"][style=\"dashed\"][shape=\"plain\"]"));
+	}
+
+	#[test]
+	fn coin_machine_dot_options() {
+		let mut still_fsm = build_fsm();
+		still_fsm.set_recent_transitions(2);
+
+		let goodcoin = Box::new(StillArguments::Coin(StillCoinType::Good));
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin, Some(goodcoin))]).unwrap();
+		while still_fsm.events_pending() {
+			assert!(still_fsm.process_event_queue().is_ok());
+		}
+
+		let dot = still_fsm.dot_string(&zipit(Box::new(StillStates::iter_variants()),
+		                                      Box::new(StillStates::iter_variant_names())),
+		                               &zipit(Box::new(StillEvents::iter_variants()),
+		                                      Box::new(StillEvents::iter_variant_names())),
+		                               &DotOptions {
+			                               highlight_current: true,
+			                               color_by_frequency: true,
+			                               recent_transitions: 5,
+			                               ..DotOptions::default()
+		                               })
+			.expect("cannot render dot");
+		assert!(dot.contains("[label=\"OpenWaitForTimeOut\"][style=\"filled\"][color=\"lightblue\"]"));
+		// only two transitions remembered, most recent one is the acceptance
+		assert!(dot.contains("Accepted\\n|AcceptMoney|\\n1x\\n#1"));
+		assert!(dot.contains("ProcessCoin\\n|GotCoin|\\n1x\\n#2"));
+		assert!(dot.contains("TimeOut\\n|Timeout|\\n0x\"][color=\"gray\"]"));
+		assert!(!dot.contains("#3"));
+	}
+
+	#[test]
+	fn coin_machine_dot_deterministic() {
+		let options = DotOptions {
+			deterministic: true,
+			..DotOptions::default()
+		};
+		let render = |fsm: &CoinStillFSM| {
+			fsm.dot_string(&zipit(Box::new(StillStates::iter_variants()),
+			                      Box::new(StillStates::iter_variant_names())),
+			               &zipit(Box::new(StillEvents::iter_variants()),
+			                      Box::new(StillEvents::iter_variant_names())),
+			               &options)
+				.expect("cannot render dot")
+		};
+
+		let dot = render(&build_fsm());
+		assert_eq!(dot, render(&build_fsm()));
+		assert!(dot.starts_with("digraph G_coin_still {"));
+
+		let nodes = dot.lines()
+			.filter(|l| !l.contains("->") && l.trim_start().starts_with("N_"))
+			.map(|l| l.trim().split('[').next().unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(nodes, vec!["N_CheckingMoney",
+		                       "N_ClosedWaitForMoney",
+		                       "N_OpenWaitForTimeOut",
+		                       "N_OpenWaitForTimeOut_Enter",
+		                       "N_OpenWaitForTimeOut_Exit"]);
+		assert!(dot.contains("N_OpenWaitForTimeOut_Enter -> N_OpenWaitForTimeOut[label=\"CountOpens\"]"));
+	}
+
+	#[test]
+	fn coin_machine_plantuml() {
+		let still_fsm = build_fsm();
+		let uml = still_fsm.plantuml_string(&zipit(Box::new(StillStates::iter_variants()),
+		                                           Box::new(StillStates::iter_variant_names())),
+		                                    &zipit(Box::new(StillEvents::iter_variants()),
+		                                           Box::new(StillEvents::iter_variant_names())))
+			.expect("cannot render plantuml");
+
+		assert!(uml.starts_with("@startuml\ntitle coin_still\n"));
+		assert!(uml.ends_with("@enduml\n"));
+		assert!(uml.contains("\n[*] --> ClosedWaitForMoney\n"));
+		assert!(uml.contains("\nOpenWaitForTimeOut : entry / CountOpens\n\
+		                      OpenWaitForTimeOut : exit / CountClose\n"));
+		assert!(uml.contains("\nClosedWaitForMoney --> CheckingMoney : GotCoin / ProcessCoin\n"));
+		assert!(uml.contains("\nCheckingMoney --> CheckingMoney : GotCoin / IgnoreAnotherCoin\n"));
+	}
+
+	#[test]
+	fn coin_machine_mermaid() {
+		let still_fsm = build_fsm();
+		let mmd = still_fsm.mermaid_string(&zipit(Box::new(StillStates::iter_variants()),
+		                                          Box::new(StillStates::iter_variant_names())),
+		                                   &zipit(Box::new(StillEvents::iter_variants()),
+		                                          Box::new(StillEvents::iter_variant_names())))
+			.expect("cannot render mermaid");
+
+		assert!(mmd.starts_with("---\ntitle: coin_still\n---\nstateDiagram-v2\n"));
+		assert!(mmd.contains("\n    [*] --> ClosedWaitForMoney\n"));
+		assert!(mmd.contains("\n    OpenWaitForTimeOut --> ClosedWaitForMoney : Timeout / TimeOut\n"));
+		assert!(mmd.ends_with(concat!("\n    note right of OpenWaitForTimeOut\n",
+		                              "        entry / CountOpens\n",
+		                              "        exit / CountClose\n",
+		                              "    end note\n")));
+	}
+
+	#[test]
+	fn coin_machine_scxml() {
+		let mut still_fsm = build_fsm();
+		still_fsm.add_enter_transition((StillStates::CheckingMoney, EntryExit::EntryTransition),
+		                               Box::new(|_| Ok(None)),
+		                               None);
+		let scxml = still_fsm.scxml_string(&zipit(Box::new(StillStates::iter_variants()),
+		                                          Box::new(StillStates::iter_variant_names())),
+		                                   &zipit(Box::new(StillEvents::iter_variants()),
+		                                          Box::new(StillEvents::iter_variant_names())))
+			.expect("cannot render scxml");
+
+		assert!(scxml.contains("name=\"coin_still\" initial=\"ClosedWaitForMoney\">"));
+		assert!(scxml.contains(concat!("  <state id=\"OpenWaitForTimeOut\">\n",
+		                               "    <onentry>\n",
+		                               "      <log label=\"CountOpens\"/>\n",
+		                               "    </onentry>\n",
+		                               "    <onexit>\n",
+		                               "      <log label=\"CountClose\"/>\n",
+		                               "    </onexit>\n")));
+		assert!(scxml.contains(concat!("    <transition event=\"GotCoin\" target=\"CheckingMoney\">\n",
+		                               "      <log label=\"ProcessCoin\"/>\n",
+		                               "    </transition>\n")));
+		// self transitions do not leave the state
+		assert!(scxml.contains("    <transition event=\"GotCoin\">\n"));
+		// unnamed ones get an empty label
+		assert!(scxml.contains(concat!("  <state id=\"CheckingMoney\">\n",
+		                               "    <onentry>\n",
+		                               "      <log label=\"\"/>\n",
+		                               "    </onentry>\n")));
+		assert!(scxml.ends_with("  </state>\n</scxml>\n"));
+	}
+
+	fn still_registry() -> Registry<StillExtState, StillStates, StillEvents, StillArguments, StillErrors> {
+		let mut registry = Registry::new();
+		registry.register_transition("ProcessCoin", Box::new(|_, _, arg: Option<Box<StillArguments>>| {
+			match arg.map(|a| *a) {
+				Some(StillArguments::Coin(StillCoinType::Good)) => {
+					Ok(Some(vec![(StillEvents::AcceptMoney, None)].into_iter().collect()))
+				}
+				_ => Ok(Some(vec![(StillEvents::RejectMoney, None)].into_iter().collect())),
+			}
+		}));
+		for name in ["Rejected", "IgnoreAnotherCoin", "TimeOut"].iter() {
+			registry.register_transition(name, Box::new(|_, _, _| Ok(None)));
+		}
+		registry.register_transition("Accepted", Box::new(|mut estate: RefMut<Box<StillExtState>>, _, _| {
+			estate.coincounter += 1;
+			Ok(None)
+		}));
+		registry.register_transition("Reject", Box::new(|_, _, _| {
+			Ok(Some(vec![(StillEvents::RejectMoney, None)].into_iter().collect()))
+		}));
+		registry.register_entryexit("CountOpens", Box::new(|mut estate: RefMut<Box<StillExtState>>| {
+			estate.opened += 1;
+			Ok(None)
+		}));
+		registry.register_entryexit("CountClose", Box::new(|mut estate: RefMut<Box<StillExtState>>| {
+			estate.closed += 1;
+			Ok(None)
+		}));
+		registry.register_transition("CountCoin", Box::new(|mut estate: RefMut<Box<StillExtState>>,
+		                                                    _, arg: Option<Box<StillArguments>>| {
+			if let Some(StillArguments::Coin(StillCoinType::Good)) = arg.map(|a| *a) {
+				estate.coincounter += 1;
+			}
+			Ok(None)
+		}));
+		registry.register_guard("Counted", Box::new(|x: &StillExtState| x.coincounter > x.opened));
+		registry.register_entryexit("Open", Box::new(|_| Ok(None)));
+		registry
+	}
+
+	fn load_still(desc: &MachineDescription) -> std::result::Result<CoinStillFSM, LoadError> {
+		let mainlog = Logger::root(Discard, o!());
+		load(desc, &still_registry(),
+		     &zipit(Box::new(StillStates::iter_variants()),
+		            Box::new(StillStates::iter_variant_names())),
+		     &zipit(Box::new(StillEvents::iter_variants()),
+		            Box::new(StillEvents::iter_variant_names())),
+		     Box::new(StillExtState {
+			     coincounter: 0,
+			     opened: 0,
+			     closed: 0,
+		     }),
+		     mainlog)
+	}
+
+	#[test]
+	fn coin_machine_load() {
+		let scxml = build_fsm().scxml_string(&zipit(Box::new(StillStates::iter_variants()),
+		                                            Box::new(StillStates::iter_variant_names())),
+		                                     &zipit(Box::new(StillEvents::iter_variants()),
+		                                            Box::new(StillEvents::iter_variant_names())))
+			.expect("cannot render scxml");
+		let desc = MachineDescription::from_scxml(&scxml).expect("cannot read scxml");
+		assert_eq!(desc, build_fsm().describe(&zipit(Box::new(StillStates::iter_variants()),
+		                                             Box::new(StillStates::iter_variant_names())),
+		                                      &zipit(Box::new(StillEvents::iter_variants()),
+		                                             Box::new(StillEvents::iter_variant_names()))));
+
+		#[cfg(feature = "serde")]
+		let desc: MachineDescription = {
+			let json = serde_json::to_string(&desc).expect("cannot serialize");
+			serde_json::from_str(&json).expect("cannot deserialize")
+		};
+
+		let mut still_fsm = load_still(&desc).expect("cannot load");
+		let goodcoin = Box::new(StillArguments::Coin(StillCoinType::Good));
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin, Some(goodcoin))]).unwrap();
+		while still_fsm.events_pending() {
+			assert!(still_fsm.process_event_queue().is_ok());
+		}
+		assert!(still_fsm.current_state() == StillStates::OpenWaitForTimeOut);
+		assert!(still_fsm.extended_state().coincounter == 1);
+		assert!(still_fsm.extended_state().opened == 1);
+
+		let mut broken = desc.clone();
+		broken.transitions[0].name = String::from("Teleport");
+		match load_still(&broken) {
+			Err(LoadError::UnknownTransition(ref n, _)) if n == "Teleport" => {}
+			_ => panic!("unknown transition not reported"),
+		}
+		let mut broken = desc.clone();
+		broken.transitions[0].to = String::from("Nowhere");
+		let err = load_still(&broken).err().expect("unknown state not reported");
+		assert_eq!(format!("{}", err), format!("unknown state `Nowhere` in transition {} --{}--> Nowhere",
+		                                      desc.transitions[0].from, desc.transitions[0].event));
+	}
+
+	#[test]
+	fn coin_machine_codegen() {
+		let mut desc = build_fsm().describe(&zipit(Box::new(StillStates::iter_variants()),
+		                                           Box::new(StillStates::iter_variant_names())),
+		                                    &zipit(Box::new(StillEvents::iter_variants()),
+		                                           Box::new(StillEvents::iter_variant_names())));
+		let options = CodegenOptions::new("StillExtState", "StillArguments", "StillErrors");
+
+		let mut out = Vec::new();
+		generate(&desc, &options, &mut out).expect("cannot generate");
+		let code = String::from_utf8(out).expect("code is not utf8");
+
+		assert!(code.contains("pub enum States {\n\tCheckingMoney,\n\tClosedWaitForMoney,\n\t\
+		                       OpenWaitForTimeOut,\n}\n"));
+		assert!(code.contains("\t\tnames.insert(Events::AcceptMoney, \"AcceptMoney\");\n"));
+		assert!(code.contains("pub fn build_fsm(extended_init: Box<StillExtState>, log: ::slog::Logger) \
+		                       -> ::extfsm::FSM<StillExtState, States, Events, StillArguments, StillErrors> {"));
+		assert!(code.contains("::extfsm::TransitionTarget::new(States::CheckingMoney, \
+		                       Box::new(handlers::process_coin), Some(\"ProcessCoin\")));"));
+		assert!(code.contains("fsm.add_enter_transition((States::OpenWaitForTimeOut, \
+		                       ::extfsm::EntryExit::ExitTransition),\n\t                         \
+		                       Box::new(handlers::count_close), Some(\"CountClose\"));"));
+
+		desc.transitions[0].to = String::from("Not A State");
+		assert!(generate(&desc, &options, &mut Vec::new()).is_err());
+	}
+
+	#[test]
+	fn coin_machine_load_unnamed() {
+		let mut still_fsm = build_fsm();
+		still_fsm.add_transition(TransitionSource::new(StillStates::ClosedWaitForMoney,
+		                                               StillEvents::Timeout),
+		                         TransitionTarget::new(StillStates::ClosedWaitForMoney,
+		                                               Box::new(|_, _, _| Ok(None)),
+		                                               None));
+		still_fsm.add_enter_transition((StillStates::CheckingMoney, EntryExit::EntryTransition),
+		                               Box::new(|_| Ok(None)),
+		                               None);
+		let (mut states, events) = still_names();
+
+		let scxml = still_fsm.scxml_string(&states, &events).unwrap();
+		let desc = MachineDescription::from_scxml(&scxml).expect("cannot read scxml");
+		assert_eq!(desc, still_fsm.describe(&states, &events));
+		assert!(desc.transitions.iter().any(|t| t.event == "Timeout" && t.name.is_empty()));
+		assert!(desc.entryexit.iter().any(|ee| ee.state == "CheckingMoney" && ee.name.is_empty()));
+
+		// names the ids are sanitized from resolve by their ids
+		states.insert(StillStates::ClosedWaitForMoney, "Closed-Wait");
+		let scxml = still_fsm.scxml_string(&states, &events).unwrap();
+		assert!(scxml.contains("<state id=\"Closed_Wait\">"));
+		let desc = MachineDescription::from_scxml(&scxml).expect("cannot read scxml");
+		let mut registry = still_registry();
+		registry.register_transition("", Box::new(|_, _, _| Ok(None)));
+		registry.register_entryexit("", Box::new(|_| Ok(None)));
+		let mut still_fsm = load(&desc, &registry, &states, &events,
+		                         Box::new(StillExtState {
+			                         coincounter: 0,
+			                         opened: 0,
+			                         closed: 0,
+		                         }),
+		                         Logger::root(Discard, o!()))
+			.expect("cannot load");
+		assert_eq!(still_fsm.current_state(), StillStates::ClosedWaitForMoney);
+		still_fsm.add_events(&mut vec![(StillEvents::Timeout, None)]).unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 1);
+	}
+
+	#[test]
+	fn coin_machine_explore() {
+		let events = vec![(StillEvents::GotCoin, Some(StillArguments::Coin(StillCoinType::Good))),
+		                  (StillEvents::GotCoin, Some(StillArguments::Coin(StillCoinType::Bad))),
+		                  (StillEvents::Timeout, None)];
+
+		let mut explorer = Explorer::new(Box::new(build_fsm), events.clone(), 4);
+		explorer.add_invariant("balanced", Box::new(|s, x: &StillExtState| {
+			let open = if *s == StillStates::OpenWaitForTimeOut { 1 } else { 0 };
+			if x.opened == x.closed + open && x.opened == x.coincounter {
+				Ok(())
+			} else {
+				Err(format!("{:?}", x))
+			}
+		}));
+		let report = explorer.explore();
+
+		assert!(report.violations.is_empty() && report.livelocks.is_empty());
+		assert!(report.bounded);
+		// checking money is only passed through while the queue drains
+		assert_eq!(report.reachable.len(), 2);
+		assert!(!report.reachable.contains(&StillStates::CheckingMoney));
+		// timeout on a closed machine is the shortest error
+		match report.errors.first() {
+			Some(&(ref trace, Errors::NoTransition(StillEvents::Timeout,
+			                                      StillStates::ClosedWaitForMoney))) => {
+				assert_eq!(*trace, vec![events[2].clone()]);
+			}
+			e => panic!("unexpected error {:?}", e),
+		}
+
+		let mut explorer = Explorer::new(Box::new(build_fsm), events.clone(), 4);
+		explorer.add_invariant("single use", Box::new(|_, x: &StillExtState| {
+			if x.coincounter < 2 { Ok(()) } else { Err(String::from("coin accepted twice")) }
+		}));
+		let report = explorer.explore();
+		assert_eq!(report.violations.len(), 1);
+		assert_eq!(report.violations[0].trace,
+		           vec![events[0].clone(), events[2].clone(), events[0].clone()]);
+
+		// without hashing every trace is followed and the shortest found first
+		let all = explorer.explore_all();
+		assert_eq!(all.violations[0].trace, report.violations[0].trace);
+		assert!(all.configurations > report.configurations);
+	}
+
+	#[cfg(feature = "proptest")]
+	#[test]
+	fn coin_machine_proptest() {
+		let events = vec![(StillEvents::GotCoin, Some(StillArguments::Coin(StillCoinType::Good))),
+		                  (StillEvents::GotCoin, Some(StillArguments::Coin(StillCoinType::Bad))),
+		                  (StillEvents::Timeout, None)];
+		let sequences = event_sequences(sample_events(events.clone()), 0..24);
+
+		let mut check = SequenceCheck::new(Box::new(build_fsm));
+		check.add_invariant("balanced", Box::new(|s, x: &StillExtState| {
+			let open = if *s == StillStates::OpenWaitForTimeOut { 1 } else { 0 };
+			if x.opened == x.closed + open && x.opened == x.coincounter {
+				Ok(())
+			} else {
+				Err(format!("{:?}", x))
+			}
+		}));
+		// timeout while closed is expected to fail
+		check.set_fail_on_error(false);
+		assert!(check.run(&mut TestRunner::deterministic(), &sequences).is_ok());
+
+		check.add_invariant("single use", Box::new(|_, x: &StillExtState| {
+			if x.coincounter < 2 { Ok(()) } else { Err(String::from("coin accepted twice")) }
+		}));
+		match check.run(&mut TestRunner::deterministic(), &sequences) {
+			Err(TestError::Fail(_, sequence)) => {
+				assert_eq!(sequence, vec![events[0].clone(), events[2].clone(), events[0].clone()]);
+			}
+			r => panic!("unexpected result {:?}", r),
+		}
+	}
+
+	#[test]
+	fn coin_machine_coverage() {
+		let goodcoin = Box::new(StillArguments::Coin(StillCoinType::Good));
+		let badcoin = Box::new(StillArguments::Coin(StillCoinType::Bad));
+		let s2n = zipit(Box::new(StillStates::iter_variants()),
+		                Box::new(StillStates::iter_variant_names()));
+		let e2n = zipit(Box::new(StillEvents::iter_variants()),
+		                Box::new(StillEvents::iter_variant_names()));
+
+		let run = |batches: Vec<Vec<(StillEvents, Option<Box<StillArguments>>)>>| {
+			let mut still_fsm = build_fsm();
+			for mut b in batches {
+				still_fsm.add_events(&mut b).unwrap();
+				while still_fsm.events_pending() {
+					assert!(still_fsm.process_event_queue().is_ok());
+				}
+			}
+			still_fsm
+		};
+
+		let mut coverage = Coverage::default();
+		let still_fsm = run(vec![vec![(StillEvents::GotCoin, Some(goodcoin.clone()))]]);
+		coverage.merge(&still_fsm.coverage());
+		coverage.merge(&run(vec![vec![(StillEvents::GotCoin, Some(badcoin.clone()))]]).coverage());
+
+		assert_eq!(coverage.covered(), (4, 9));
+		assert_eq!(coverage.transition_count(StillStates::ClosedWaitForMoney,
+		                                     StillEvents::GotCoin), Some(2));
+		assert_eq!(coverage.entryexit_count(StillStates::OpenWaitForTimeOut,
+		                                    EntryExit::ExitTransition), Some(0));
+		assert_eq!(coverage.transition_count(StillStates::ClosedWaitForMoney,
+		                                     StillEvents::Timeout), None);
+		let untested = coverage.untested(&s2n, &e2n);
+		assert!(untested.contains(&String::from("CheckingMoney |GotCoin| -> CheckingMoney \
+		                                         IgnoreAnotherCoin")));
+		assert!(untested.contains(&String::from("Exit OpenWaitForTimeOut CountClose")));
+		assert!(coverage.report_string(&s2n, &e2n).unwrap()
+			.contains("        2x ClosedWaitForMoney |GotCoin| -> CheckingMoney ProcessCoin"));
+		let dot = still_fsm.coverage_dot_string(&s2n, &e2n, &coverage, &DotOptions::default())
+			.unwrap();
+		assert_eq!(dot.matches("UNTESTED").count(), 5);
+		assert!(dot.contains("color=\"red\""));
+
+		coverage.merge(&run(vec![vec![(StillEvents::GotCoin, Some(goodcoin.clone())),
+		                              (StillEvents::GotCoin, Some(badcoin))],
+		                         vec![(StillEvents::GotCoin, Some(goodcoin))],
+		                         vec![(StillEvents::Timeout, None)]]).coverage());
+		assert!(coverage.is_complete());
+		coverage.assert_complete(&s2n, &e2n);
+	}
+
+	#[test]
+	fn coin_machine_scenario() {
+		let goodcoin = StillArguments::Coin(StillCoinType::Good);
+
+		let mut s = Scenario::new("good coin");
+		s.expect_state(StillStates::ClosedWaitForMoney)
+			.post(StillEvents::GotCoin, Some(goodcoin.clone()))
+			.expect_state(StillStates::CheckingMoney)
+			.expect_queued(vec![StillEvents::AcceptMoney])
+			.process()
+			.expect_state(StillStates::OpenWaitForTimeOut)
+			.expect_queued(vec![])
+			.expect("accepted", Box::new(|x: &StillExtState| x.coincounter == 1 && x.opened == 1))
+			.post(StillEvents::Timeout, None)
+			.expect_state(StillStates::ClosedWaitForMoney)
+			.expect("closed", Box::new(|x: &StillExtState| x.closed == 1))
+			.post(StillEvents::Timeout, None)
+			.expect_error();
+		assert_eq!(s.run(&mut build_fsm()), Ok(4));
+		s.assert_passes(&mut build_fsm());
+
+		let mut s = Scenario::new("coin opens at once");
+		s.post(StillEvents::GotCoin, Some(goodcoin))
+			.expect_state(StillStates::OpenWaitForTimeOut);
+		let divergence = s.run(&mut build_fsm()).unwrap_err();
+		assert_eq!(divergence.step, 1);
+		assert_eq!(divergence.to_string(),
+		           "scenario `coin opens at once` diverged at step 1 (post GotCoin), state:\n  \
+		            expected: OpenWaitForTimeOut\n  \
+		            found:    CheckingMoney");
+
+		let mut s = Scenario::new("coin missing");
+		s.post(StillEvents::GotCoin, None);
+		let divergence = s.run(&mut build_fsm()).unwrap_err();
+		assert_eq!(divergence.expectation, "processing");
+		assert_eq!(divergence.found, "InternalError(GotCoin, ClosedWaitForMoney, CoinArgumentMissing)");
+
+		let mut s = Scenario::new("bad coin");
+		s.post(StillEvents::GotCoin, Some(StillArguments::Coin(StillCoinType::Bad)))
+			.expect("counted", Box::new(|x: &StillExtState| x.coincounter == 1));
+		let divergence = s.run(&mut build_fsm()).unwrap_err();
+		assert_eq!(divergence.expected, "`counted` holds");
+		assert_eq!(divergence.found, "StillExtState { coincounter: 0, opened: 0, closed: 0 }");
+	}
+
+	#[test]
+	fn coin_machine_send() {
+		let still_fsms = (0..4).map(|i| {
+			let mut still_fsm = SendFSM::<StillExtState,
+				StillStates,
+				StillEvents,
+				StillArguments,
+				StillErrors>::new(StillStates::ClosedWaitForMoney,
+			                      Box::new(StillExtState {
+				                      coincounter: 0,
+				                      opened: 0,
+				                      closed: 0,
+			                      }),
+			                      &format!("coin_still_{}", i),
+			                      Logger::root(Discard, o!()));
+			still_fsm.add_transition(TransitionSource::new(StillStates::ClosedWaitForMoney,
+			                                               StillEvents::GotCoin),
+			                         SendTransitionTarget::new(StillStates::OpenWaitForTimeOut,
+			                                                   Box::new(|mut estate, _, _| {
+				                                                   estate.coincounter += 1;
+				                                                   Ok(None)
+			                                                   }),
+			                                                   Some("Accepted")));
+			// another coin opens again
+			still_fsm.add_transition(TransitionSource::new(StillStates::OpenWaitForTimeOut,
+			                                               StillEvents::GotCoin),
+			                         SendTransitionTarget::new(StillStates::OpenWaitForTimeOut,
+			                                                   Box::new(|_, _, _| Ok(None)),
+			                                                   Some("Reopen"))
+				                         .with_kind(TransitionKind::External));
+			still_fsm.add_transition(TransitionSource::new(StillStates::OpenWaitForTimeOut,
+			                                               StillEvents::Timeout),
+			                         SendTransitionTarget::new(StillStates::ClosedWaitForMoney,
+			                                                   Box::new(|_, _, _| Ok(None)),
+			                                                   Some("TimeOut")));
+			still_fsm.add_enter_transition((StillStates::OpenWaitForTimeOut,
+			                                EntryExit::EntryTransition),
+			                               Box::new(|mut estate| {
+				                               estate.opened += 1;
+				                               Ok(None)
+			                               }),
+			                               Some("CountOpens"));
+			still_fsm
+		}).collect::<Vec<_>>();
+
+		let workers = still_fsms.into_iter().enumerate().map(|(i, mut still_fsm)| {
+			std::thread::spawn(move || {
+				for _ in 0..i {
+					still_fsm.add_events(&mut vec![(StillEvents::GotCoin, None),
+					                               (StillEvents::GotCoin, None),
+					                               (StillEvents::Timeout, None)]).unwrap();
+					while still_fsm.events_pending() {
+						assert!(still_fsm.process_event_queue().is_ok());
+					}
+				}
+				still_fsm
+			})
+		}).collect::<Vec<_>>();
+
+		for (i, w) in workers.into_iter().enumerate() {
+			let still_fsm = w.join().expect("worker failed");
+			assert_eq!(still_fsm.current_state(), StillStates::ClosedWaitForMoney);
+			assert_eq!(still_fsm.extended_state().coincounter, i as u32);
+			assert_eq!(still_fsm.into_inner().extended_state().opened, 2 * i as u32);
+		}
+	}
+
+	#[test]
+	fn coin_machine_handle() {
+		let recorder = Recorder::new();
+		let mut still_fsm = build_fsm();
+		still_fsm.set_recorder(Some(Box::new(recorder.clone())));
+		let handle = still_fsm.handle();
+
+		// the I/O thread posts, tells the machine's thread to process and waits
+		// until it did
+		let (post_tx, post_rx) = mpsc::channel();
+		let (done_tx, done_rx) = mpsc::channel();
+		let io = {
+			let handle = handle.clone();
+			std::thread::spawn(move || {
+				let coin = Box::new(StillArguments::Coin(StillCoinType::Good));
+				for e in [(StillEvents::GotCoin, Some(coin)), (StillEvents::Timeout, None)] {
+					assert_eq!(handle.post(vec![e]), 1);
+					post_tx.send(()).unwrap();
+					done_rx.recv().unwrap();
+				}
+			})
+		};
+
+		let mut states = vec![];
+		while post_rx.recv().is_ok() {
+			assert!(still_fsm.events_pending());
+			assert!(still_fsm.pending_events().is_empty());
+			while still_fsm.events_pending() {
+				assert!(still_fsm.process_event_queue().is_ok());
+			}
+			states.push(still_fsm.current_state());
+			done_tx.send(()).unwrap();
+		}
+		io.join().unwrap();
+
+		assert_eq!(states, vec![StillStates::OpenWaitForTimeOut, StillStates::ClosedWaitForMoney]);
+		assert_eq!(still_fsm.extended_state().coincounter, 1);
+		assert_eq!(handle.pending(), 0);
+
+		// posted events are recorded and replay
+		let recording = recorder.recording();
+		assert_eq!(recording.entries.iter()
+			           .filter(|e| matches!(**e, RecordEntry::Event(_, _)))
+			           .count(), 2);
+		assert!(replay(&mut build_fsm(), &recording).is_ok());
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn coin_machine_async() {
+		let recorder = Recorder::new();
+		let mut still_fsm = build_fsm();
+		still_fsm.set_recorder(Some(Box::new(recorder.clone())));
+		let mut still_fsm = AsyncFSM::new(still_fsm);
+
+		// coins are checked by another thread
+		assert!(!still_fsm.add_async_transition(
+			TransitionSource::new(StillStates::ClosedWaitForMoney, StillEvents::GotCoin),
+			StillStates::CheckingMoney,
+			TransitionKind::Internal,
+			Box::new(|_, _, arg| {
+				let (tx, rx) = oneshot::channel();
+				std::thread::spawn(move || {
+					let verdict = match arg.as_deref() {
+						Some(&StillArguments::Coin(StillCoinType::Good)) => StillEvents::AcceptMoney,
+						_ => StillEvents::RejectMoney,
+					};
+					tx.send(verdict).unwrap();
+				});
+				Box::pin(rx.map(|verdict| match verdict {
+					Ok(ev) => Ok(Some(vec![(ev, None)].into_iter().collect())),
+					Err(_) => Err(Errors::TransitionFailure),
+				}))
+			}),
+			Some("ProcessCoin")));
+
+		let (tx, rx) = async_mpsc::unbounded();
+		for e in [(StillEvents::GotCoin, Some(Box::new(StillArguments::Coin(StillCoinType::Good)))),
+		          (StillEvents::Timeout, None),
+		          (StillEvents::GotCoin, Some(Box::new(StillArguments::Coin(StillCoinType::Bad))))] {
+			tx.unbounded_send(e).unwrap();
+		}
+		drop(tx);
+
+		assert_eq!(executor::block_on(still_fsm.run(rx)).unwrap(), 5);
+		assert_eq!(still_fsm.current_state(), StillStates::ClosedWaitForMoney);
+		assert_eq!(still_fsm.extended_state().coincounter, 1);
+		// recorded generation by generation like `process_event_queue` does
+		let recording = recorder.recording();
+		assert_eq!(recording.states(), vec![&StillStates::CheckingMoney,
+		                                    &StillStates::OpenWaitForTimeOut,
+		                                    &StillStates::ClosedWaitForMoney,
+		                                    &StillStates::CheckingMoney,
+		                                    &StillStates::ClosedWaitForMoney]);
+		// and replays against the machine checking coins synchronously
+		assert_eq!(replay(&mut build_fsm(), &recording), Ok(5));
+
+		// timeout while closed still fails
+		let (tx, rx) = async_mpsc::unbounded();
+		tx.unbounded_send((StillEvents::Timeout, None)).unwrap();
+		match executor::block_on(still_fsm.run(rx)) {
+			Err(Errors::NoTransition(StillEvents::Timeout, StillStates::ClosedWaitForMoney)) => {}
+			r => panic!("unexpected result {:?}", r),
+		}
+
+		// another coin while open closes and opens again
+		let mut still_fsm = AsyncFSM::new(build_fsm());
+		assert!(!still_fsm.add_async_transition(
+			TransitionSource::new(StillStates::OpenWaitForTimeOut, StillEvents::GotCoin),
+			StillStates::OpenWaitForTimeOut,
+			TransitionKind::External,
+			Box::new(|_, _, _| Box::pin(futures::future::ready(Ok(None)))),
+			Some("Reopen")));
+		let (tx, rx) = async_mpsc::unbounded();
+		for _ in 0..2 {
+			tx.unbounded_send((StillEvents::GotCoin,
+			                   Some(Box::new(StillArguments::Coin(StillCoinType::Good))))).unwrap();
+		}
+		drop(tx);
+		assert_eq!(executor::block_on(still_fsm.run(rx)).unwrap(), 3);
+		assert_eq!(still_fsm.current_state(), StillStates::OpenWaitForTimeOut);
+		assert_eq!((still_fsm.extended_state().opened, still_fsm.extended_state().closed), (2, 1));
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn coin_machine_async_handle() {
+		let mut still_fsm = build_fsm();
+		let (checked_tx, checked_rx) = mpsc::channel();
+		still_fsm.add_enter_transition((StillStates::CheckingMoney, EntryExit::EntryTransition),
+		                               Box::new(move |_| {
+			                               checked_tx.send(()).unwrap();
+			                               Ok(None)
+		                               }),
+		                               Some("Checking"));
+		let mut still_fsm = AsyncFSM::new(still_fsm);
+
+		// the stream stays idle while a coin is posted through a handle
+		let (tx, rx) = async_mpsc::unbounded::<(StillEvents, Option<Box<StillArguments>>)>();
+		let handle = still_fsm.handle();
+		let poster = std::thread::spawn(move || {
+			std::thread::sleep(std::time::Duration::from_millis(50));
+			handle.post(vec![(StillEvents::GotCoin,
+			                  Some(Box::new(StillArguments::Coin(StillCoinType::Good))))]);
+			let checked = checked_rx.recv_timeout(std::time::Duration::from_secs(5)).is_ok();
+			drop(tx);
+			checked
+		});
+
+		assert_eq!(executor::block_on(still_fsm.run(rx)).unwrap(), 2);
+		assert!(poster.join().unwrap());
+		assert_eq!(still_fsm.current_state(), StillStates::OpenWaitForTimeOut);
+	}
+
+	#[test]
+	fn coin_machine_runtime() {
+		let goodcoin = Box::new(StillArguments::Coin(StillCoinType::Good));
+		let mut runtime = Runtime::new(Logger::root(Discard, o!()));
+
+		// on timeout a still returns a coin, which the router hands over to the
+		// next one
+		for id in ["a", "b"] {
+			let mut still_fsm = build_fsm();
+			still_fsm.add_transition(TransitionSource::new(StillStates::OpenWaitForTimeOut,
+			                                               StillEvents::Timeout),
+			                         TransitionTarget::new(StillStates::ClosedWaitForMoney,
+			                                               Box::new(|_, _, _| {
+				                                               Ok(Some(vec![(StillEvents::GotCoin,
+				                                                             Some(Box::new(
+					                                                             StillArguments::Coin(
+						                                                             StillCoinType::Good))))]
+					                                               .into_iter()
+					                                               .collect()))
+			                                               }),
+			                                               Some("PassOn")));
+			assert!(runtime.add_machine(id, still_fsm).is_none());
+		}
+		assert_eq!(runtime.ids(), &["a", "b"]);
+		assert!(runtime.set_router(Some(Box::new(|from: &&str, ev: &StillEvents, _| {
+			match (*from, ev) {
+				("a", StillEvents::GotCoin) => Some("b"),
+				("b", StillEvents::GotCoin) => Some("c"),
+				_ => None,
+			}
+		}))).is_none());
+
+		runtime.post("a", StillEvents::GotCoin, Some(goodcoin));
+		assert_eq!(runtime.run(16).unwrap(), 2);
+		// timeout in a, GotCoin and AcceptMoney in b
+		runtime.post("a", StillEvents::Timeout, None);
+		assert_eq!(runtime.run(16).unwrap(), 3);
+		assert!(runtime.is_idle());
+		assert_eq!(runtime.machine(&"a").unwrap().current_state(), StillStates::ClosedWaitForMoney);
+		assert_eq!(runtime.machine(&"b").unwrap().current_state(), StillStates::OpenWaitForTimeOut);
+		assert_eq!(runtime.machine(&"b").unwrap().extended_state().coincounter, 1);
+
+		// b passes on to a machine that does not exist
+		runtime.post("b", StillEvents::Timeout, None);
+		match runtime.run(16) {
+			Err(RunErrors { processed: 1, ref errors })
+			if matches!(errors[..], [RuntimeError::UnknownMachine("c", StillEvents::GotCoin)]) => {}
+			r => panic!("unexpected result {:?}", r.map_err(|_| ())),
+		}
+
+		// failing machines are removed, the others still process in the round
+		runtime.post("a", StillEvents::Timeout, None);
+		runtime.post("b", StillEvents::GotCoin,
+		             Some(Box::new(StillArguments::Coin(StillCoinType::Good))));
+		match runtime.run(16) {
+			Err(RunErrors { processed: 1, ref errors })
+			if matches!(errors[..], [RuntimeError::Failed("a", Errors::NoTransition(
+				StillEvents::Timeout, StillStates::ClosedWaitForMoney))]) => {}
+			r => panic!("unexpected result {:?}", r.map_err(|_| ())),
+		}
+		assert_eq!(runtime.ids(), &["b"]);
+		assert_eq!(runtime.machine(&"b").unwrap().current_state(), StillStates::CheckingMoney);
+		assert_eq!(runtime.run(16).unwrap(), 1);
+		assert!(runtime.is_idle());
+	}
+
+	#[test]
+	fn coin_machine_spawn() {
+		let goodcoin = Box::new(StillArguments::Coin(StillCoinType::Good));
+		let mut runtime = Runtime::new(Logger::root(Discard, o!()));
+		let (exits, exited) = mpsc::channel();
+
+		// on timeout the parent spawns a child and hands it a coin, the parent gets a
+		// coin back once the child opened
+		let mut still_fsm = build_fsm();
+		let (postbox, spawner, tx) = (runtime.postbox(), runtime.spawner(), exits.clone());
+		let spawn = move |_: RefMut<Box<StillExtState>>, _, _| {
+			let tx = tx.clone();
+			spawner.spawn("a", "child", build_fsm(), vec![StillStates::OpenWaitForTimeOut],
+			              Box::new(move |exit| {
+				              tx.send(exit).unwrap();
+				              (StillEvents::GotCoin, Some(Box::new(StillArguments::Coin(StillCoinType::Good))))
+			              }));
+			postbox.post("child", StillEvents::GotCoin,
+			             Some(Box::new(StillArguments::Coin(StillCoinType::Good))));
+			Ok(None)
+		};
+		still_fsm.add_transition(TransitionSource::new(StillStates::OpenWaitForTimeOut,
+		                                               StillEvents::Timeout),
+		                         TransitionTarget::new(StillStates::ClosedWaitForMoney,
+		                                               Box::new(spawn),
+		                                               Some("Spawn")));
+		runtime.add_machine("a", still_fsm);
+		runtime.add_machine("b", build_fsm());
+
+		runtime.post("a", StillEvents::GotCoin, Some(goodcoin));
+		assert_eq!(runtime.run(16).unwrap(), 2);
+		// timeout in a, GotCoin and AcceptMoney in the child and back in a
+		runtime.post("a", StillEvents::Timeout, None);
+		assert_eq!(runtime.run(16).unwrap(), 5);
+		match exited.try_recv() {
+			Ok(ChildExit::Finished("child", StillStates::OpenWaitForTimeOut)) => {}
+			r => panic!("unexpected exit {:?}", r.map_err(|_| ())),
+		}
+		assert_eq!(runtime.ids(), &["a", "b"]);
+		assert_eq!(runtime.parent(&"child"), None);
+		assert_eq!(runtime.machine(&"a").unwrap().extended_state().coincounter, 2);
+
+		// a spawn under an id taken is dropped without holding up the others, a
+		// child spawned in one of its final states finishes right away
+		let (spawner, tx) = (runtime.spawner(), exits.clone());
+		spawner.spawn("b", "a", build_fsm(), vec![], Box::new(|_| (StillEvents::Timeout, None)));
+		spawner.spawn("b", "done", build_fsm(), vec![StillStates::ClosedWaitForMoney],
+		              Box::new(move |exit| {
+			              tx.send(exit).unwrap();
+			              (StillEvents::GotCoin, Some(Box::new(StillArguments::Coin(StillCoinType::Good))))
+		              }));
+		match runtime.run(16) {
+			Err(RunErrors { ref errors, .. }) if matches!(errors[..], [RuntimeError::Duplicate("a")]) => {}
+			r => panic!("unexpected result {:?}", r.map_err(|_| ())),
+		}
+		match exited.try_recv() {
+			Ok(ChildExit::Finished("done", StillStates::ClosedWaitForMoney)) => {}
+			r => panic!("unexpected exit {:?}", r.map_err(|_| ())),
+		}
+		runtime.run(16).unwrap();
+		assert_eq!(runtime.ids(), &["a", "b"]);
+		assert_eq!(runtime.machine(&"b").unwrap().current_state(), StillStates::OpenWaitForTimeOut);
+
+		// failing children notify their parent instead of failing the runtime
+		runtime.spawner().spawn("b", "child", build_fsm(), vec![], Box::new(move |exit| {
+			exits.send(exit).unwrap();
+			(StillEvents::GotCoin, Some(Box::new(StillArguments::Coin(StillCoinType::Good))))
+		}));
+		runtime.post("child", StillEvents::Timeout, None);
+		assert_eq!(runtime.run(16).unwrap(), 2);
+		match exited.try_recv() {
+			Ok(ChildExit::Failed("child", Errors::NoTransition(StillEvents::Timeout,
+			                                                   StillStates::ClosedWaitForMoney))) => {}
+			r => panic!("unexpected exit {:?}", r.map_err(|_| ())),
+		}
+		assert_eq!(runtime.ids(), &["a", "b"]);
+		assert_eq!(runtime.machine(&"b").unwrap().current_state(), StillStates::OpenWaitForTimeOut);
+
+		// tearing down the parent tears down its children
+		runtime.spawner().spawn("b", "child", build_fsm(), vec![], Box::new(|_| (StillEvents::Timeout, None)));
+		runtime.run(16).unwrap();
+		assert_eq!(runtime.parent(&"child"), Some(&"b"));
+		runtime.spawner().teardown("b");
+		runtime.run(16).unwrap();
+		assert_eq!(runtime.ids(), &["a"]);
+	}
+
+	#[test]
+	fn coin_machine_supervisor() {
+		let goodcoin = || Some(Box::new(StillArguments::Coin(StillCoinType::Good)));
+		let policy = RestartPolicy {
+			max_restarts: 1,
+			period: std::time::Duration::from_secs(3600),
+			restore_checkpoint: true,
+		};
+		let mut supervisor = Supervisor::new(Box::new(build_fsm), policy, Logger::root(Discard, o!()));
+		let handle = supervisor.handle();
+		let recorder = Recorder::new();
+		supervisor.set_recorder(Some(Box::new(recorder.clone())));
+
+		// checking money with the coin still queued is not checkpointed
+		supervisor.add_events(&mut vec![(StillEvents::GotCoin, goodcoin())]).unwrap();
+		assert_eq!(supervisor.process_event_queue().unwrap(), 1);
+		assert!(supervisor.last_checkpoint().is_none());
+		assert_eq!(supervisor.process_event_queue().unwrap(), 1);
+		supervisor.add_events(&mut vec![(StillEvents::Timeout, None)]).unwrap();
+		assert_eq!(supervisor.process_event_queue().unwrap(), 1);
+
+		// timeout while checking money restarts closed from the last checkpoint
+		supervisor.add_events(&mut vec![(StillEvents::GotCoin, goodcoin()),
+		                                (StillEvents::Timeout, None)]).unwrap();
+		assert_eq!(supervisor.process_event_queue().unwrap(), 0);
+		assert_eq!(supervisor.restarts(), 1);
+		assert_eq!(supervisor.current_state(), StillStates::ClosedWaitForMoney);
+		assert_eq!(supervisor.extended_state().coincounter, 1);
+		assert_eq!(supervisor.last_checkpoint().unwrap().extended_state.closed, 1);
+
+		// handles survive the restart
+		handle.post(vec![(StillEvents::GotCoin, goodcoin())]);
+		assert_eq!(supervisor.process_event_queue().unwrap(), 1);
+		assert_eq!(supervisor.process_event_queue().unwrap(), 1);
+		assert_eq!(supervisor.extended_state().coincounter, 2);
+		// the recorder moved along and saw the restore, which replays
+		let recording = recorder.recording();
+		assert_eq!(recording.states(), vec![&StillStates::CheckingMoney,
+		                                    &StillStates::OpenWaitForTimeOut,
+		                                    &StillStates::ClosedWaitForMoney,
+		                                    &StillStates::CheckingMoney,
+		                                    &StillStates::ClosedWaitForMoney,
+		                                    &StillStates::CheckingMoney,
+		                                    &StillStates::OpenWaitForTimeOut]);
+		let mut replayed_fsm = build_fsm();
+		assert_eq!(replay(&mut replayed_fsm, &recording), Ok(7));
+		assert_eq!(replayed_fsm.extended_state().coincounter, 2);
+		// without the checkpoints the restart goes back to the start
+		let mut changed = recording;
+		changed.entries.retain(|e| !matches!(*e, RecordEntry::Checkpoint(_)));
+		let mut replayed_fsm = build_fsm();
+		assert_eq!(replay(&mut replayed_fsm, &changed), Ok(7));
+		assert_eq!(replayed_fsm.extended_state().coincounter, 1);
+
+		// failing again within the period escalates
+		supervisor.add_events(&mut vec![(StillEvents::Timeout, None), (StillEvents::Timeout, None)]).unwrap();
+		match supervisor.process_event_queue() {
+			Err(Escalation {
+				    restarts: 1,
+				    error: Errors::NoTransition(StillEvents::Timeout, StillStates::ClosedWaitForMoney),
+				    ..
+			    }) => {}
+			r => panic!("unexpected result {:?}", r.map_err(|_| ())),
+		}
+	}
+
+	#[test]
+	fn coin_machine_dynamic() {
+		let mut still_fsm = build_fsm();
+		let (states, events) = still_names();
+
+		// good coins open right away without passing through checking money
+		still_fsm.add_transition(TransitionSource::new(StillStates::ClosedWaitForMoney,
+		                                               StillEvents::GotCoin),
+		                         TransitionTarget::dynamic(StillStates::OpenWaitForTimeOut,
+		                                                   vec![StillStates::ClosedWaitForMoney],
+		                                                   Box::new(|mut estate: RefMut<Box<StillExtState>>,
+		                                                             _, arg: Option<Box<StillArguments>>| {
+			                                                   match arg.map(|a| *a) {
+				                                                   Some(StillArguments::Coin(StillCoinType::Good)) => {
+					                                                   estate.coincounter += 1;
+					                                                   Ok((StillStates::OpenWaitForTimeOut, None))
+				                                                   }
+				                                                   Some(_) => Ok((StillStates::ClosedWaitForMoney, None)),
+				                                                   None => Ok((StillStates::CheckingMoney, None)),
+			                                                   }
+		                                                   }),
+		                                                   Some("SortCoin")));
+
+		let desc = still_fsm.describe(&states, &events);
+		assert_eq!(desc.transitions.iter().filter(|t| t.name == "SortCoin").count(), 2);
+		match load_still(&desc) {
+			Err(LoadError::UnknownDynamic(ref n, _)) if n == "SortCoin" => {}
+			r => panic!("unexpected result {:?}", r.map(|_| ())),
+		}
+
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin,
+		                                Some(Box::new(StillArguments::Coin(StillCoinType::Good))))])
+			.unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 1);
+		assert_eq!(still_fsm.current_state(), StillStates::OpenWaitForTimeOut);
+		assert_eq!(still_fsm.extended_state().opened, 1);
+		assert_eq!(still_fsm.extended_state().coincounter, 1);
+
+		still_fsm.add_events(&mut vec![(StillEvents::Timeout, None),
+		                               (StillEvents::GotCoin,
+		                                Some(Box::new(StillArguments::Coin(StillCoinType::Bad))))])
+			.unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 2);
+		assert_eq!(still_fsm.current_state(), StillStates::ClosedWaitForMoney);
+		assert_eq!(still_fsm.extended_state().closed, 1);
+
+		// targets not declared fail the machine
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin, None)]).unwrap();
+		match still_fsm.process_event_queue() {
+			Err(Errors::InvalidTarget(StillEvents::GotCoin, StillStates::ClosedWaitForMoney,
+			                          StillStates::CheckingMoney)) => {}
+			r => panic!("unexpected result {:?}", r),
+		}
+
+		// dynamic transitions that may remain in their state only leave it once
+		// they chose to, the others leave it first like fixed transitions
+		let order = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+		for (key, what) in [((StillStates::ClosedWaitForMoney, EntryExit::ExitTransition), "exit Closed"),
+		                    ((StillStates::ClosedWaitForMoney, EntryExit::EntryTransition), "enter Closed"),
+		                    ((StillStates::OpenWaitForTimeOut, EntryExit::ExitTransition), "exit Open"),
+		                    ((StillStates::OpenWaitForTimeOut, EntryExit::EntryTransition), "enter Open")] {
+			let order = order.clone();
+			still_fsm.add_enter_transition(key, Box::new(move |_| {
+				order.borrow_mut().push(what);
+				Ok(None)
+			}), None);
+		}
+		for (from, event, targets, what) in [(StillStates::ClosedWaitForMoney, StillEvents::GotCoin,
+		                                      vec![StillStates::ClosedWaitForMoney], "SortCoin"),
+		                                     (StillStates::OpenWaitForTimeOut, StillEvents::Timeout,
+		                                      vec![], "TimeOut")] {
+			let order = order.clone();
+			let to = match from {
+				StillStates::ClosedWaitForMoney => StillStates::OpenWaitForTimeOut,
+				_ => StillStates::ClosedWaitForMoney,
+			};
+			still_fsm.add_transition(TransitionSource::new(from, event),
+			                         TransitionTarget::dynamic(to.clone(), targets, Box::new(move |_, _, _| {
+				                         order.borrow_mut().push(what);
+				                         Ok((to.clone(), None))
+			                         }), Some(what)));
+		}
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin, None), (StillEvents::Timeout, None)])
+			.unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 2);
+		assert_eq!(order.take(), vec!["SortCoin", "exit Closed", "enter Open",
+		                                 "exit Open", "TimeOut", "enter Closed"]);
+	}
+
+	#[test]
+	fn coin_machine_choice() {
+		let mut still_fsm = build_fsm();
+
+		// checking money only branches on whether the coin has been counted
+		still_fsm.add_transition(TransitionSource::new(StillStates::ClosedWaitForMoney,
+		                                               StillEvents::GotCoin),
+		                         TransitionTarget::new(StillStates::CheckingMoney,
+		                                               Box::new(|mut estate: RefMut<Box<StillExtState>>,
+		                                                         _, arg: Option<Box<StillArguments>>| {
+			                                               if let Some(StillArguments::Coin(StillCoinType::Good)) =
+				                                               arg.map(|a| *a) {
+				                                               estate.coincounter += 1;
+			                                               }
+			                                               Ok(None)
+		                                               }),
+		                                               Some("CountCoin")));
+		assert!(still_fsm.add_choice(StillStates::CheckingMoney,
+		                             vec![ChoiceBranch::new(StillStates::OpenWaitForTimeOut,
+		                                                    Box::new(|x: &StillExtState| {
+			                                                    x.coincounter > x.opened
+		                                                    }),
+		                                                    Some("Counted"))],
+		                             StillStates::ClosedWaitForMoney));
+
+		// no follow up event is queued for the branch
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin,
+		                                Some(Box::new(StillArguments::Coin(StillCoinType::Good))))])
+			.unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 1);
+		assert!(!still_fsm.events_pending());
+		assert_eq!(still_fsm.current_state(), StillStates::OpenWaitForTimeOut);
+		assert_eq!(still_fsm.extended_state().opened, 1);
+
+		still_fsm.add_events(&mut vec![(StillEvents::Timeout, None),
+		                               (StillEvents::GotCoin,
+		                                Some(Box::new(StillArguments::Coin(StillCoinType::Bad))))])
+			.unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 2);
+		assert!(!still_fsm.events_pending());
+		assert_eq!(still_fsm.current_state(), StillStates::ClosedWaitForMoney);
+		assert_eq!(still_fsm.extended_state().coincounter, 1);
+
+		// choices are exported as such and load with their guards bound by name
+		let (states, events) = still_names();
+		let uml = still_fsm.plantuml_string(&states, &events).unwrap();
+		assert!(uml.contains("state CheckingMoney <<choice>>\n"));
+		assert!(uml.contains("CheckingMoney --> OpenWaitForTimeOut : [Counted]\n\
+		                      CheckingMoney --> ClosedWaitForMoney : [else]\n"));
+		let mmd = still_fsm.mermaid_string(&states, &events).unwrap();
+		assert!(mmd.contains("    state CheckingMoney <<choice>>\n"));
+		let scxml = still_fsm.scxml_string(&states, &events).unwrap();
+		assert!(scxml.contains("    <transition cond=\"Counted\" target=\"OpenWaitForTimeOut\"/>\n\
+		                        \x20   <transition target=\"ClosedWaitForMoney\"/>\n"));
+		let desc = MachineDescription::from_scxml(&scxml).unwrap();
+		assert_eq!(desc, still_fsm.describe(&states, &events));
+
+		let mut out = Vec::new();
+		generate(&desc, &CodegenOptions::new("StillExtState", "StillArguments", "StillErrors"), &mut out)
+			.unwrap();
+		assert!(String::from_utf8(out).unwrap().contains(
+			"\tfsm.add_choice(States::CheckingMoney,\n\
+			 \t               vec![::extfsm::ChoiceBranch::new(States::OpenWaitForTimeOut, \
+			 Box::new(handlers::counted), Some(\"Counted\"))],\n\
+			 \t               States::ClosedWaitForMoney);\n"));
+
+		let mut loaded = load_still(&desc).unwrap();
+		loaded.add_events(&mut vec![(StillEvents::GotCoin,
+		                             Some(Box::new(StillArguments::Coin(StillCoinType::Good))))])
+			.unwrap();
+		assert_eq!(loaded.process_event_queue().unwrap(), 1);
+		assert_eq!(loaded.current_state(), StillStates::OpenWaitForTimeOut);
+
+		let mut broken = desc.clone();
+		broken.choices.retain(|b| b.guard.is_some());
+		match load_still(&broken) {
+			Err(LoadError::NoOtherwise(ref c)) if c == "choice CheckingMoney" => {}
+			r => panic!("unexpected result {:?}", r.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn link_machine_history() {
+		#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+		enum LinkStates {
+			Down,
+			Init,
+			Up,
+			Suspended,
+			Resumed,
+		}
+		#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+		enum LinkEvents {
+			Start,
+			Hello,
+			Suspend,
+			Resume,
+		}
+
+		// counts how often the link came up
+		let mut link_fsm = FSM::<u32, LinkStates, LinkEvents, (), ()>::new(LinkStates::Down, Box::new(0),
+		                                                                   "link", Logger::root(Discard, o!()));
+		for (from, event, to) in [(LinkStates::Down, LinkEvents::Start, LinkStates::Init),
+		                          (LinkStates::Init, LinkEvents::Hello, LinkStates::Up),
+		                          (LinkStates::Init, LinkEvents::Suspend, LinkStates::Suspended),
+		                          (LinkStates::Up, LinkEvents::Suspend, LinkStates::Suspended),
+		                          (LinkStates::Suspended, LinkEvents::Resume, LinkStates::Resumed)] {
+			link_fsm.add_transition(TransitionSource::new(from, event),
+			                        TransitionTarget::new(to, Box::new(|_, _, _| Ok(None)), None));
+		}
+		link_fsm.add_enter_transition((LinkStates::Up, EntryExit::EntryTransition),
+		                              Box::new(|mut ups: RefMut<Box<u32>>| {
+			                              **ups += 1;
+			                              Ok(None)
+		                              }),
+		                              Some("CountUp"));
+		// the initial state has to be one of the group
+		assert!(!link_fsm.add_history(LinkStates::Resumed, HistoryKind::Shallow,
+		                              vec![LinkStates::Init, LinkStates::Up], LinkStates::Down));
+		assert!(link_fsm.add_history(LinkStates::Resumed, HistoryKind::Shallow,
+		                             vec![LinkStates::Down, LinkStates::Init, LinkStates::Up],
+		                             LinkStates::Down));
+
+		let run = |fsm: &mut FSM<u32, LinkStates, LinkEvents, (), ()>, events: Vec<LinkEvents>| {
+			fsm.add_events(&mut events.into_iter().map(|e| (e, None)).collect()).unwrap();
+			fsm.process_event_queue().unwrap();
+			fsm.current_state()
+		};
+
+		assert_eq!(run(&mut link_fsm, vec![LinkEvents::Start, LinkEvents::Suspend]), LinkStates::Suspended);
+		assert_eq!(run(&mut link_fsm, vec![LinkEvents::Resume]), LinkStates::Init);
+		assert_eq!(run(&mut link_fsm, vec![LinkEvents::Hello, LinkEvents::Suspend]), LinkStates::Suspended);
+		// resuming enters the last state again
+		assert_eq!(run(&mut link_fsm, vec![LinkEvents::Resume]), LinkStates::Up);
+		assert_eq!(**link_fsm.extended_state(), 2);
+
+		let states = [(LinkStates::Down, "Down"), (LinkStates::Init, "Init"), (LinkStates::Up, "Up"),
+		              (LinkStates::Suspended, "Suspended"), (LinkStates::Resumed, "Resumed")]
+			.iter().cloned().collect();
+		let dot = link_fsm.dot_string(&states, &HashMap::new(), &DotOptions::default()).unwrap();
+		assert!(dot.contains("[label=\"Resumed\"][shape=\"circle\"]"));
+	}
+
+	#[test]
+	fn player_machine_deep_history() {
+		#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+		enum PlayerStates {
+			Idle,
+			First,
+			Second,
+			Stopped,
+			Tracks,
+			Shallow,
+			Deep,
+		}
+		#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+		enum PlayerEvents {
+			Play,
+			Next,
+			Off,
+			On,
+			Resume,
+		}
+
+		// the tracks form a group nested in the group of the player
+		let build = || {
+			let mut fsm = FSM::<(), PlayerStates, PlayerEvents, (), ()>::new(PlayerStates::Idle, Box::new(()),
+			                                                                 "player", Logger::root(Discard, o!()));
+			for (from, event, to) in [(PlayerStates::Idle, PlayerEvents::Play, PlayerStates::Tracks),
+			                          (PlayerStates::First, PlayerEvents::Next, PlayerStates::Second),
+			                          (PlayerStates::Idle, PlayerEvents::Off, PlayerStates::Stopped),
+			                          (PlayerStates::First, PlayerEvents::Off, PlayerStates::Stopped),
+			                          (PlayerStates::Second, PlayerEvents::Off, PlayerStates::Stopped),
+			                          (PlayerStates::Stopped, PlayerEvents::On, PlayerStates::Shallow),
+			                          (PlayerStates::Stopped, PlayerEvents::Resume, PlayerStates::Deep)] {
+				fsm.add_transition(TransitionSource::new(from, event),
+				                   TransitionTarget::new(to, Box::new(|_, _, _| Ok(None)), Some("Go")));
+			}
+			assert!(fsm.add_history(PlayerStates::Tracks, HistoryKind::Shallow,
+			                        vec![PlayerStates::First, PlayerStates::Second], PlayerStates::First));
+			for (history, kind) in [(PlayerStates::Shallow, HistoryKind::Shallow),
+			                        (PlayerStates::Deep, HistoryKind::Deep)] {
+				assert!(fsm.add_history(history, kind, vec![PlayerStates::Idle, PlayerStates::Tracks],
+				                        PlayerStates::Idle));
+			}
+			fsm
+		};
+		let run = |fsm: &mut FSM<(), PlayerStates, PlayerEvents, (), ()>, events: Vec<PlayerEvents>| {
+			fsm.add_events(&mut events.into_iter().map(|e| (e, None)).collect()).unwrap();
+			fsm.process_event_queue().unwrap();
+			fsm.current_state()
+		};
+
+		let mut player = build();
+		assert_eq!(run(&mut player, vec![PlayerEvents::Play, PlayerEvents::Next, PlayerEvents::Off]),
+		           PlayerStates::Stopped);
+		let checkpoint = player.checkpoint();
+		// deep history resumes the track, shallow history starts the tracks over
+		assert_eq!(run(&mut player, vec![PlayerEvents::Resume]), PlayerStates::Second);
+		assert_eq!(run(&mut player, vec![PlayerEvents::Off, PlayerEvents::On]), PlayerStates::First);
+
+		// the histories are restored with the checkpoint
+		let mut restored = build();
+		restored.restore(checkpoint);
+		assert_eq!(run(&mut restored, vec![PlayerEvents::Resume]), PlayerStates::Second);
+
+		// configurations differing in histories only are explored each
+		let events = vec![PlayerEvents::Play, PlayerEvents::Next, PlayerEvents::Off, PlayerEvents::On,
+		                  PlayerEvents::Resume].into_iter().map(|e| (e, None)).collect();
+		let report = Explorer::new(Box::new(build), events, 4).explore();
+		assert!(report.configurations > report.reachable.len());
+
+		// histories are exported with their kind and group and load again
+		let states = [(PlayerStates::Idle, "Idle"), (PlayerStates::First, "First"),
+		              (PlayerStates::Second, "Second"), (PlayerStates::Stopped, "Stopped"),
+		              (PlayerStates::Tracks, "Tracks"), (PlayerStates::Shallow, "Shallow"),
+		              (PlayerStates::Deep, "Deep")].iter().cloned().collect();
+		let events = [(PlayerEvents::Play, "Play"), (PlayerEvents::Next, "Next"), (PlayerEvents::Off, "Off"),
+		              (PlayerEvents::On, "On"), (PlayerEvents::Resume, "Resume")].iter().cloned().collect();
+		let uml = player.plantuml_string(&states, &events).unwrap();
+		assert!(uml.contains("state Deep <<history*>>\n") && uml.contains("state Tracks <<history>>\n"));
+		assert!(uml.contains("Deep --> Idle\nnote right of Deep : resumes Idle, Tracks\n"));
+		let mmd = player.mermaid_string(&states, &events).unwrap();
+		assert!(mmd.contains("    state \"Deep [H*]\" as Deep\n"));
+		let scxml = player.scxml_string(&states, &events).unwrap();
+		assert!(scxml.contains("  <history id=\"Deep\" type=\"deep\" fsm:members=\"Idle Tracks\">\n\
+		                        \x20   <transition target=\"Idle\"/>\n"));
+		let desc = MachineDescription::from_scxml(&scxml).unwrap();
+		assert_eq!(desc.histories, player.describe(&states, &events).histories);
+
+		let mut out = Vec::new();
+		generate(&desc, &CodegenOptions::new("()", "()", "()"), &mut out).unwrap();
+		assert!(String::from_utf8(out).unwrap().contains(
+			"\tfsm.add_history(States::Deep, ::extfsm::HistoryKind::Deep, vec![States::Idle, States::Tracks], \
+			 States::Idle);\n"));
+
+		let mut registry = Registry::new();
+		registry.register_transition("Go", Box::new(|_, _, _| Ok(None)));
+		let mut loaded = load(&desc, &registry, &states, &events, Box::new(()), Logger::root(Discard, o!()))
+			.unwrap();
+		assert_eq!(run(&mut loaded, vec![PlayerEvents::Play, PlayerEvents::Next, PlayerEvents::Off,
+		                                 PlayerEvents::Resume]), PlayerStates::Second);
+	}
+
+	#[test]
+	fn coin_machine_external() {
+		let mut still_fsm = build_fsm();
+		let (states, events) = still_names();
+		let goodcoin = || Some(Box::new(StillArguments::Coin(StillCoinType::Good)));
+
+		// another coin while open closes and opens again
+		still_fsm.add_transition(TransitionSource::new(StillStates::OpenWaitForTimeOut,
+		                                               StillEvents::GotCoin),
+		                         TransitionTarget::new(StillStates::OpenWaitForTimeOut,
+		                                               Box::new(|_, _, _| Ok(None)),
+		                                               Some("Reopen"))
+			                         .with_kind(TransitionKind::External));
+
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin, goodcoin())]).unwrap();
+		while still_fsm.events_pending() {
+			still_fsm.process_event_queue().unwrap();
+		}
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin, goodcoin())]).unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 1);
+		assert_eq!(still_fsm.current_state(), StillStates::OpenWaitForTimeOut);
+		assert_eq!(still_fsm.extended_state().opened, 2);
+		assert_eq!(still_fsm.extended_state().closed, 1);
+
+		let scxml = still_fsm.scxml_string(&states, &events).unwrap();
+		assert!(scxml.contains("<transition event=\"GotCoin\" target=\"OpenWaitForTimeOut\">"));
+		let desc = MachineDescription::from_scxml(&scxml).unwrap();
+		assert!(desc.transitions.iter().any(|t| t.from == "OpenWaitForTimeOut" && t.event == "GotCoin" &&
+			t.kind == TransitionKind::External));
+		assert!(desc.transitions.iter().any(|t| t.from == "CheckingMoney" && t.event == "GotCoin" &&
+			t.kind == TransitionKind::Internal));
+	}
+
+	#[test]
+	fn coin_machine_completion() {
+		let mut still_fsm = build_fsm();
+
+		// good coins are counted on the way in, checking money then completes by itself
+		still_fsm.add_transition(TransitionSource::new(StillStates::ClosedWaitForMoney,
+		                                               StillEvents::GotCoin),
+		                         TransitionTarget::new(StillStates::CheckingMoney,
+		                                               Box::new(|mut estate: RefMut<Box<StillExtState>>,
+		                                                         _, arg: Option<Box<StillArguments>>| {
+			                                               if let Some(StillArguments::Coin(StillCoinType::Good)) =
+				                                               arg.map(|a| *a) {
+				                                               estate.coincounter += 1;
+			                                               }
+			                                               Ok(None)
+		                                               }),
+		                                               Some("CountCoin")));
+		let completion = CompletionTransition::new(StillStates::OpenWaitForTimeOut,
+		                                           Box::new(|_| Ok(None)),
+		                                           Some("Open"))
+			.with_guard(Box::new(|x: &StillExtState| x.coincounter > x.opened), Some("Counted"));
+		assert!(still_fsm.add_completion(StillStates::CheckingMoney, completion));
+
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin,
+		                                Some(Box::new(StillArguments::Coin(StillCoinType::Good))))])
+			.unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 1);
+		assert!(!still_fsm.events_pending());
+		assert_eq!(still_fsm.current_state(), StillStates::OpenWaitForTimeOut);
+		assert_eq!(still_fsm.extended_state().opened, 1);
+
+		// the guard does not hold for bad coins, the machine stays in checking money
+		still_fsm.add_events(&mut vec![(StillEvents::Timeout, None),
+		                               (StillEvents::GotCoin,
+		                                Some(Box::new(StillArguments::Coin(StillCoinType::Bad))))])
+			.unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 2);
+		assert!(!still_fsm.events_pending());
+		assert_eq!(still_fsm.current_state(), StillStates::CheckingMoney);
+		assert_eq!(still_fsm.extended_state().coincounter, 1);
+
+		// completions are exported with their guard and load with it bound by name
+		let (states, events) = still_names();
+		let uml = still_fsm.plantuml_string(&states, &events).unwrap();
+		assert!(uml.contains("CheckingMoney -[dotted]-> OpenWaitForTimeOut : [Counted] / Open\n"));
+		let mmd = still_fsm.mermaid_string(&states, &events).unwrap();
+		assert!(mmd.contains("    CheckingMoney --> OpenWaitForTimeOut : [Counted] / Open\n"));
+		let scxml = still_fsm.scxml_string(&states, &events).unwrap();
+		assert!(scxml.contains("    <transition cond=\"Counted\" target=\"OpenWaitForTimeOut\">\n\
+		                        \x20     <log label=\"Open\"/>\n"));
+		let desc = MachineDescription::from_scxml(&scxml).unwrap();
+		assert_eq!(desc, still_fsm.describe(&states, &events));
+
+		let mut out = Vec::new();
+		generate(&desc, &CodegenOptions::new("StillExtState", "StillArguments", "StillErrors"), &mut out)
+			.unwrap();
+		assert!(String::from_utf8(out).unwrap().contains(
+			"\tfsm.add_completion(States::CheckingMoney,\n\
+			 \t                   ::extfsm::CompletionTransition::new(States::OpenWaitForTimeOut, \
+			 Box::new(handlers::open), Some(\"Open\"))\
+			 .with_guard(Box::new(handlers::counted), Some(\"Counted\")));\n"));
+
+		let mut loaded = load_still(&desc).unwrap();
+		loaded.add_events(&mut vec![(StillEvents::GotCoin,
+		                             Some(Box::new(StillArguments::Coin(StillCoinType::Good))))])
+			.unwrap();
+		assert_eq!(loaded.process_event_queue().unwrap(), 1);
+		assert_eq!(loaded.current_state(), StillStates::OpenWaitForTimeOut);
+	}
+
+	#[test]
+	fn coin_machine_entryexit_context() {
+		let mut still_fsm = build_fsm();
+
+		// records what leaving money collection and checking money are caused by
+		let (tx, rx) = mpsc::channel();
+		for case in [(StillStates::ClosedWaitForMoney, EntryExit::ExitTransition),
+		             (StillStates::CheckingMoney, EntryExit::EntryTransition)].iter().cloned() {
+			let tx = tx.clone();
+			let kind = case.1.clone();
+			assert!(still_fsm.add_enter_transition_with_context(
+				case,
+				Box::new(move |_, c: &EntryExitContext<StillStates, StillEvents, StillArguments>| {
+					tx.send((kind.clone(), c.event.cloned(), c.from.clone(), c.to.clone(), c.arg.cloned()))
+						.unwrap();
+					Ok(None)
+				}),
+				Some("Trace")));
+		}
+
+		still_fsm.add_events(&mut vec![(StillEvents::GotCoin,
+		                                Some(Box::new(StillArguments::Coin(StillCoinType::Bad))))])
+			.unwrap();
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 1);
+		assert_eq!(still_fsm.process_event_queue().unwrap(), 1);
+		assert_eq!(still_fsm.current_state(), StillStates::ClosedWaitForMoney);
+
+		let bad = Some(StillArguments::Coin(StillCoinType::Bad));
+		let seen = rx.try_iter().collect::<Vec<_>>();
+		assert_eq!(seen,
+		           vec![(EntryExit::ExitTransition, Some(StillEvents::GotCoin),
+		                 StillStates::ClosedWaitForMoney, StillStates::CheckingMoney, bad.clone()),
+		                (EntryExit::EntryTransition, Some(StillEvents::GotCoin),
+		                 StillStates::ClosedWaitForMoney, StillStates::CheckingMoney, bad)]);
 	}
 }
@@ -0,0 +1,796 @@
+//! Building an FSM from an external description of its structure.
+//!
+//! A `MachineDescription` names states, events and transitions. With the `serde`
+//! feature it can be deserialized from any serde format (e.g. JSON or TOML),
+//! alternatively it can be read from the SCXML written by `FSM::render_scxml`.
+//! `load` binds the transition names to closures registered in a `Registry` and
+//! resolves state and event names with the same name maps used for the exports,
+//! state ids written by the text formats resolve as well.
+//! Transitions described more than once from the same state upon the same event
+//! form a single dynamic transition that can end in any of their targets. Choice
+//! pseudo-states are described by their branches, guards are bound to predicates
+//! registered by name as well. History pseudo-states are described with their
+//! group. Completion transitions take the extended state only and are bound to
+//! entry/exit transitions registered by name.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use slog::Logger;
+
+use super::{FSM, EntryExit, TransitionFn, DynamicTransitionFn, EntryExitTransitionFn, Guard,
+            TransitionKind, TransitionSource, TransitionTarget, ChoiceBranch, HistoryKind,
+            CompletionTransition};
+use export::export_id;
+
+/// a transition upon an event, `name` refers to a closure in the `Registry`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TransitionDescription {
+	pub from: String,
+	pub event: String,
+	pub to: String,
+	pub name: String,
+	/// whether a self transition leaves its state, only relevant if `to` is `from`
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub kind: TransitionKind,
+}
+
+/// an entry or exit transition, `name` refers to a closure in the `Registry`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EntryExitDescription {
+	pub state: String,
+	pub kind: EntryExit,
+	pub name: String,
+}
+
+/// a branch of a choice pseudo-state, `guard` refers to a predicate in the `Registry`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChoiceDescription {
+	pub choice: String,
+	pub to: String,
+	/// None for the branch taken if no guard holds
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub guard: Option<String>,
+}
+
+/// a completion transition, `name` refers to an entry/exit transition and
+/// `guard` to a predicate in the `Registry`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CompletionDescription {
+	pub state: String,
+	pub to: String,
+	pub name: String,
+	/// None if the transition is not guarded
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub guard: Option<String>,
+}
+
+/// a history pseudo-state with the group of states it resumes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HistoryDescription {
+	pub history: String,
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub kind: HistoryKind,
+	pub members: Vec<String>,
+	/// one of the members
+	pub initial: String,
+}
+
+/// structure of a machine by names
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MachineDescription {
+	pub name: String,
+	pub start: String,
+	/// states of the machine, states only used in transitions do not need to be listed
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub states: Vec<String>,
+	/// events of the machine, events only used in transitions do not need to be listed
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub events: Vec<String>,
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub transitions: Vec<TransitionDescription>,
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub entryexit: Vec<EntryExitDescription>,
+	/// branches of all choices, those of a choice in the order they are tried
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub choices: Vec<ChoiceDescription>,
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub histories: Vec<HistoryDescription>,
+	#[cfg_attr(feature = "serde", serde(default))]
+	pub completions: Vec<CompletionDescription>,
+}
+
+/// errors that can occur reading a description or building the FSM from it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+	/// state name cannot be resolved, second value says where it has been used
+	UnknownState(String, String),
+	/// event name cannot be resolved, second value says where it has been used
+	UnknownEvent(String, String),
+	/// no transition registered under the name, second value says where it has been used
+	UnknownTransition(String, String),
+	/// no dynamic transition registered under the name of a transition described
+	/// with several targets, second value says where it has been used
+	UnknownDynamic(String, String),
+	/// no entry/exit transition registered under the name, second value says where
+	/// it has been used
+	UnknownEntryExit(String, String),
+	/// no guard registered under the name, second value says where it has been used
+	UnknownGuard(String, String),
+	/// choice described without a branch taken if no guard holds
+	NoOtherwise(String),
+	/// transition, entry/exit transition or branch taken if no guard holds
+	/// described more than once
+	Duplicate(String),
+	/// the document cannot be read as description
+	Parse(String),
+}
+
+impl fmt::Display for LoadError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			LoadError::UnknownState(ref n, ref ctx) => write!(f, "unknown state `{}` in {}", n, ctx),
+			LoadError::UnknownEvent(ref n, ref ctx) => write!(f, "unknown event `{}` in {}", n, ctx),
+			LoadError::UnknownTransition(ref n, ref ctx) => {
+				write!(f, "no transition registered as `{}` for {}", n, ctx)
+			}
+			LoadError::UnknownDynamic(ref n, ref ctx) => {
+				write!(f, "no dynamic transition registered as `{}` for {}", n, ctx)
+			}
+			LoadError::UnknownEntryExit(ref n, ref ctx) => {
+				write!(f, "no entry/exit transition registered as `{}` for {}", n, ctx)
+			}
+			LoadError::UnknownGuard(ref n, ref ctx) => write!(f, "no guard registered as `{}` for {}", n, ctx),
+			LoadError::NoOtherwise(ref ctx) => write!(f, "{} without branch taken if no guard holds", ctx),
+			LoadError::Duplicate(ref ctx) => write!(f, "{} described more than once", ctx),
+			LoadError::Parse(ref why) => write!(f, "cannot read description: {}", why),
+		}
+	}
+}
+
+impl Error for LoadError {}
+
+/// map of names to shareable transitions
+type NamedTransitions<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> =
+HashMap<String,
+	Rc<TransitionFn<ExtendedState,
+		EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>>>;
+
+/// map of names to shareable dynamic transitions
+type NamedDynamicTransitions<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> =
+HashMap<String,
+	Rc<DynamicTransitionFn<ExtendedState,
+		EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>>>;
+
+/// map of names to shareable entry/exit transitions
+type NamedEntryExitTransitions<ExtendedState,
+                               StateType,
+                               EventType,
+                               TransitionFnArguments,
+                               ErrorType> =
+HashMap<String,
+	Rc<EntryExitTransitionFn<ExtendedState,
+		EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType>>>;
+
+/// map of names to shareable guards
+type NamedGuards<ExtendedState> = HashMap<String, Rc<Guard<ExtendedState>>>;
+
+/// named closures transitions of a description are bound to
+pub struct Registry<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	transitions: NamedTransitions<ExtendedState,
+		StateType,
+		EventType,
+		TransitionFnArguments,
+		ErrorType>,
+	dynamic: NamedDynamicTransitions<ExtendedState,
+		StateType,
+		EventType,
+		TransitionFnArguments,
+		ErrorType>,
+	entryexit: NamedEntryExitTransitions<ExtendedState,
+		StateType,
+		EventType,
+		TransitionFnArguments,
+		ErrorType>,
+	guards: NamedGuards<ExtendedState>,
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> Default
+for Registry<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	fn default() -> Registry<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		Registry {
+			transitions: HashMap::new(),
+			dynamic: HashMap::new(),
+			entryexit: HashMap::new(),
+			guards: HashMap::new(),
+		}
+	}
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+Registry<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	pub fn new() -> Registry<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		Registry::default()
+	}
+
+	/// registers a transition closure under `name`
+	///
+	/// `returns` - TRUE if registered, FALSE if a previous has been overwritten!
+	pub fn register_transition(&mut self, name: &str,
+	                           transfn: Box<TransitionFn<ExtendedState,
+		                           EventType,
+		                           StateType,
+		                           TransitionFnArguments,
+		                           ErrorType>>) -> bool {
+		self.transitions.insert(String::from(name), Rc::from(transfn)).is_none()
+	}
+
+	/// registers a closure choosing the end state under `name`, bound to
+	/// transitions described with several targets
+	///
+	/// `returns` - TRUE if registered, FALSE if a previous has been overwritten!
+	pub fn register_dynamic(&mut self, name: &str,
+	                        transfn: Box<DynamicTransitionFn<ExtendedState,
+		                        EventType,
+		                        StateType,
+		                        TransitionFnArguments,
+		                        ErrorType>>) -> bool {
+		self.dynamic.insert(String::from(name), Rc::from(transfn)).is_none()
+	}
+
+	/// registers an entry/exit transition closure under `name`
+	///
+	/// `returns` - TRUE if registered, FALSE if a previous has been overwritten!
+	pub fn register_entryexit(&mut self, name: &str,
+	                          trans: Box<EntryExitTransitionFn<ExtendedState,
+		                          EventType,
+		                          StateType,
+		                          TransitionFnArguments,
+		                          ErrorType>>) -> bool {
+		self.entryexit.insert(String::from(name), Rc::from(trans)).is_none()
+	}
+
+	/// registers a guard of choice branches under `name`
+	///
+	/// `returns` - TRUE if registered, FALSE if a previous has been overwritten!
+	pub fn register_guard(&mut self, name: &str, guard: Box<Guard<ExtendedState>>) -> bool {
+		self.guards.insert(String::from(name), Rc::from(guard)).is_none()
+	}
+}
+
+impl MachineDescription {
+	/// transitions grouped by the state and event they are taken from in the order
+	/// the groups are first described, groups of more than one are dynamic
+	pub(crate) fn transition_groups(&self) -> Vec<Vec<&TransitionDescription>> {
+		let mut groups: Vec<Vec<&TransitionDescription>> = Vec::new();
+		for t in self.transitions.iter() {
+			match groups.iter_mut().find(|g| g[0].from == t.from && g[0].event == t.event) {
+				Some(g) => g.push(t),
+				None => groups.push(vec![t]),
+			}
+		}
+		groups
+	}
+
+	/// branches grouped by their choice in the order the choices are first described
+	pub(crate) fn choice_groups(&self) -> Vec<Vec<&ChoiceDescription>> {
+		let mut groups: Vec<Vec<&ChoiceDescription>> = Vec::new();
+		for b in self.choices.iter() {
+			match groups.iter_mut().find(|g| g[0].choice == b.choice) {
+				Some(g) => g.push(b),
+				None => groups.push(vec![b]),
+			}
+		}
+		groups
+	}
+}
+
+/// inverts a name map so names can be resolved, also by the ids the text formats
+/// turn them into unless different names turn into the same id
+fn names2keys<K: Clone>(names: &HashMap<K, &'static str>) -> HashMap<String, K> {
+	let mut keys = HashMap::new();
+	let mut ambiguous = HashSet::new();
+	for (k, n) in names.iter() {
+		let id = export_id(n);
+		if keys.insert(id.clone(), k.clone()).is_some() {
+			ambiguous.insert(id);
+		}
+	}
+	for id in ambiguous.iter() {
+		keys.remove(id);
+	}
+	keys.extend(names.iter().map(|(k, n)| (String::from(*n), k.clone())));
+	keys
+}
+
+fn resolve<K: Clone>(names: &HashMap<String, K>, name: &str) -> Option<K> {
+	names.get(name).cloned()
+}
+
+/// builds an FSM from a description, the first name that cannot be resolved is
+/// reported together with where it has been used.
+///
+///   * `registry` - closures the transition names are bound to
+///   * `state2name`, `event2name` - names of the states and events as used for
+///     `dotfile` and the other exports
+pub fn load<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>(
+	desc: &MachineDescription,
+	registry: &Registry<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+	state2name: &HashMap<StateType, &'static str>,
+	event2name: &HashMap<EventType, &'static str>,
+	extended_init: Box<ExtendedState>,
+	log: Logger)
+	-> Result<FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+		LoadError>
+	where StateType: Clone + Eq + Hash + Sized + 'static,
+	      EventType: Clone + Eq + Hash + Sized + 'static,
+	      ExtendedState: 'static,
+	      TransitionFnArguments: 'static,
+	      ErrorType: 'static
+{
+	let states = names2keys(state2name);
+	let events = names2keys(event2name);
+
+	let start = resolve(&states, &desc.start)
+		.ok_or_else(|| LoadError::UnknownState(desc.start.clone(), String::from("start")))?;
+	for s in desc.states.iter() {
+		resolve(&states, s)
+			.ok_or_else(|| LoadError::UnknownState(s.clone(), String::from("states")))?;
+	}
+	for e in desc.events.iter() {
+		resolve(&events, e)
+			.ok_or_else(|| LoadError::UnknownEvent(e.clone(), String::from("events")))?;
+	}
+
+	let mut fsm = FSM::new(start, extended_init, &desc.name, log);
+
+	for group in desc.transition_groups() {
+		let t = group[0];
+		let ctx = format!("transition {} --{}--> {}", t.from, t.event, t.to);
+		let from = resolve(&states, &t.from)
+			.ok_or_else(|| LoadError::UnknownState(t.from.clone(), ctx.clone()))?;
+		let event = resolve(&events, &t.event)
+			.ok_or_else(|| LoadError::UnknownEvent(t.event.clone(), ctx.clone()))?;
+
+		let mut targets = Vec::new();
+		for d in group.iter() {
+			let ctx = format!("transition {} --{}--> {}", d.from, d.event, d.to);
+			let to = resolve(&states, &d.to)
+				.ok_or_else(|| LoadError::UnknownState(d.to.clone(), ctx.clone()))?;
+			// all targets of a dynamic transition are bound to the same closure
+			if d.name != t.name || targets.contains(&to) {
+				return Err(LoadError::Duplicate(ctx));
+			}
+			targets.push(to);
+		}
+
+		let target = if group.len() > 1 {
+			let transfn = registry.dynamic.get(&t.name).cloned()
+				.ok_or_else(|| LoadError::UnknownDynamic(t.name.clone(), ctx.clone()))?;
+			let first = targets.remove(0);
+			TransitionTarget::dynamic(first, targets, Box::new(move |x, e, a| transfn(x, e, a)),
+			                          Some(&t.name))
+		} else {
+			let transfn = registry.transitions.get(&t.name).cloned()
+				.ok_or_else(|| LoadError::UnknownTransition(t.name.clone(), ctx.clone()))?;
+			TransitionTarget::new(targets.remove(0),
+			                      Box::new(move |x, e, a| transfn(x, e, a)),
+			                      Some(&t.name))
+		};
+		let kind = if group.iter().any(|d| d.kind == TransitionKind::External) {
+			TransitionKind::External
+		} else {
+			TransitionKind::Internal
+		};
+		fsm.add_transition(TransitionSource::new(from, event), target.with_kind(kind));
+	}
+
+	for ee in desc.entryexit.iter() {
+		let ctx = format!("{:?} of {}", ee.kind, ee.state);
+		let state = resolve(&states, &ee.state)
+			.ok_or_else(|| LoadError::UnknownState(ee.state.clone(), ctx.clone()))?;
+		let trans = registry.entryexit.get(&ee.name).cloned()
+			.ok_or_else(|| LoadError::UnknownEntryExit(ee.name.clone(), ctx.clone()))?;
+
+		if !fsm.add_enter_transition((state, ee.kind.clone()),
+		                             Box::new(move |x| trans(x)),
+		                             Some(&ee.name)) {
+			return Err(LoadError::Duplicate(ctx));
+		}
+	}
+
+	for group in desc.choice_groups() {
+		let ctx = format!("choice {}", group[0].choice);
+		let choice = resolve(&states, &group[0].choice)
+			.ok_or_else(|| LoadError::UnknownState(group[0].choice.clone(), ctx.clone()))?;
+
+		let mut branches = Vec::new();
+		let mut otherwise = None;
+		for b in group.iter() {
+			let ctx = format!("choice {} --> {}", b.choice, b.to);
+			let to = resolve(&states, &b.to)
+				.ok_or_else(|| LoadError::UnknownState(b.to.clone(), ctx.clone()))?;
+			match b.guard {
+				Some(ref name) => {
+					let guard = registry.guards.get(name).cloned()
+						.ok_or_else(|| LoadError::UnknownGuard(name.clone(), ctx.clone()))?;
+					branches.push(ChoiceBranch::new(to, Box::new(move |x| guard(x)), Some(name)));
+				}
+				None if otherwise.is_none() => otherwise = Some(to),
+				None => return Err(LoadError::Duplicate(ctx)),
+			}
+		}
+
+		let otherwise = otherwise.ok_or_else(|| LoadError::NoOtherwise(ctx.clone()))?;
+		if !fsm.add_choice(choice, branches, otherwise) {
+			return Err(LoadError::Duplicate(ctx));
+		}
+	}
+
+	for h in desc.histories.iter() {
+		let ctx = format!("history {}", h.history);
+		let history = resolve(&states, &h.history)
+			.ok_or_else(|| LoadError::UnknownState(h.history.clone(), ctx.clone()))?;
+		let mut members = Vec::new();
+		for m in h.members.iter() {
+			members.push(resolve(&states, m)
+				.ok_or_else(|| LoadError::UnknownState(m.clone(), ctx.clone()))?);
+		}
+		let initial = resolve(&states, &h.initial)
+			.filter(|i| members.contains(i))
+			.ok_or_else(|| LoadError::UnknownState(h.initial.clone(), format!("members of {}", ctx)))?;
+		if !fsm.add_history(history, h.kind, members, initial) {
+			return Err(LoadError::Duplicate(ctx));
+		}
+	}
+
+	for c in desc.completions.iter() {
+		let ctx = format!("completion {} --> {}", c.state, c.to);
+		let state = resolve(&states, &c.state)
+			.ok_or_else(|| LoadError::UnknownState(c.state.clone(), ctx.clone()))?;
+		let to = resolve(&states, &c.to)
+			.ok_or_else(|| LoadError::UnknownState(c.to.clone(), ctx.clone()))?;
+		let trans = registry.entryexit.get(&c.name).cloned()
+			.ok_or_else(|| LoadError::UnknownEntryExit(c.name.clone(), ctx.clone()))?;
+
+		let mut completion = CompletionTransition::new(to, Box::new(move |x| trans(x)), Some(&c.name));
+		if let Some(ref name) = c.guard {
+			let guard = registry.guards.get(name).cloned()
+				.ok_or_else(|| LoadError::UnknownGuard(name.clone(), ctx.clone()))?;
+			completion = completion.with_guard(Box::new(move |x| guard(x)), Some(name));
+		}
+		if !fsm.add_completion(state, completion) {
+			return Err(LoadError::Duplicate(ctx));
+		}
+	}
+
+	Ok(fsm)
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	/// describes the structure of the machine so it can be saved and loaded again.
+	/// Transitions and guards without a name are described with an empty name,
+	/// dynamic transitions once per target.
+	pub fn describe(&self,
+	                state2name: &HashMap<StateType, &'static str>,
+	                event2name: &HashMap<EventType, &'static str>)
+	                -> MachineDescription {
+		let m = self.export(state2name, event2name);
+
+		let mut events = event2name.values().map(|e| String::from(*e)).collect::<Vec<_>>();
+		events.sort();
+
+		MachineDescription {
+			name: String::from(m.name),
+			start: String::from(m.start),
+			states: m.states.iter().map(|s| String::from(*s)).collect(),
+			events,
+			transitions: m.transitions.iter().map(|t| {
+				TransitionDescription {
+					from: String::from(t.from),
+					event: String::from(t.event),
+					to: String::from(t.to),
+					name: String::from(t.name.unwrap_or("")),
+					kind: if t.external { TransitionKind::External } else { TransitionKind::Internal },
+				}
+			}).collect(),
+			entryexit: m.entryexit.iter().map(|ee| {
+				EntryExitDescription {
+					state: String::from(ee.state),
+					kind: ee.kind.clone(),
+					name: String::from(ee.name.unwrap_or("")),
+				}
+			}).collect(),
+			choices: m.branches.iter().map(|b| {
+				ChoiceDescription {
+					choice: String::from(b.choice),
+					to: String::from(b.to),
+					guard: if b.otherwise { None } else { Some(String::from(b.guard.unwrap_or(""))) },
+				}
+			}).collect(),
+			histories: m.histories.iter().map(|h| {
+				HistoryDescription {
+					history: String::from(h.history),
+					kind: h.kind,
+					members: h.members.iter().map(|m| String::from(*m)).collect(),
+					initial: String::from(h.initial),
+				}
+			}).collect(),
+			completions: m.completions.iter().map(|c| {
+				CompletionDescription {
+					state: String::from(c.from),
+					to: String::from(c.to),
+					name: String::from(c.name.unwrap_or("")),
+					guard: c.guard.map(String::from),
+				}
+			}).collect(),
+		}
+	}
+}
+
+/// element of an SCXML state the next `<log>` names
+enum Named {
+	Transition(TransitionDescription),
+	EntryExit(EntryExit),
+	Completion(CompletionDescription),
+}
+
+/// a start, end or empty element tag of an XML document
+struct XmlTag {
+	name: String,
+	attrs: HashMap<String, String>,
+	closing: bool,
+	empty: bool,
+}
+
+fn xml_unescape(s: &str) -> String {
+	s.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&apos;", "'")
+		.replace("&amp;", "&")
+}
+
+/// splits an XML document into its tags, text, comments and declarations are skipped
+fn xml_tags(doc: &str) -> Result<Vec<XmlTag>, LoadError> {
+	let mut tags = Vec::new();
+	let mut rest = doc;
+
+	while let Some(start) = rest.find('<') {
+		rest = &rest[start..];
+		let (skip, end) = if rest.starts_with("<!--") {
+			(true, "-->")
+		} else if rest.starts_with("<?") {
+			(true, "?>")
+		} else {
+			(false, ">")
+		};
+		let close = rest.find(end)
+			.ok_or_else(|| LoadError::Parse(String::from("unterminated tag")))?;
+		let inner = &rest[1..close];
+		rest = &rest[close + end.len()..];
+		if skip {
+			continue;
+		}
+
+		let closing = inner.starts_with('/');
+		let empty = inner.ends_with('/');
+		let inner = inner.trim_start_matches('/').trim_end_matches('/').trim();
+		let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+		let name = String::from(&inner[..name_end]);
+		let mut attrs = HashMap::new();
+
+		let mut a = inner[name_end..].trim_start();
+		while !a.is_empty() {
+			let eq = a.find('=')
+				.ok_or_else(|| LoadError::Parse(format!("attribute without value in <{}>", name)))?;
+			let key = a[..eq].trim();
+			let v = a[eq + 1..].trim_start();
+			let quote = v.chars().next()
+				.filter(|c| *c == '"' || *c == '\'')
+				.ok_or_else(|| LoadError::Parse(format!("unquoted attribute {} in <{}>", key, name)))?;
+			let vend = v[1..].find(quote)
+				.ok_or_else(|| LoadError::Parse(format!("unterminated attribute {} in <{}>", key, name)))?;
+			attrs.insert(String::from(key), xml_unescape(&v[1..vend + 1]));
+			a = v[vend + 2..].trim_start();
+		}
+
+		tags.push(XmlTag {
+			name,
+			attrs,
+			closing,
+			empty,
+		});
+	}
+
+	Ok(tags)
+}
+
+impl MachineDescription {
+	/// reads a description from flat SCXML as written by `FSM::render_scxml`. Names
+	/// of transitions are taken from `<log label="name"/>` in transitions and
+	/// `<onentry>`/`<onexit>` blocks, they are empty without, transitions without
+	/// target stay in their state. States are named by their ids, `load` resolves
+	/// them like the names they have been written for.
+	/// Transitions without event are completion transitions if they are named,
+	/// branches of choices otherwise, either guarded by their `cond`. `<history>`
+	/// elements take their group from `fsm:members`. Nested states are not
+	/// supported.
+	pub fn from_scxml(doc: &str) -> Result<MachineDescription, LoadError> {
+		let mut desc = MachineDescription::default();
+		let mut state: Option<String> = None;
+		let mut named: Option<Named> = None;
+		let mut label: Option<String> = None;
+		// history the default transition of which is still to come
+		let mut history: Option<HistoryDescription> = None;
+
+		for tag in xml_tags(doc)? {
+			let attr = |n: &str| -> Result<String, LoadError> {
+				tag.attrs.get(n).cloned()
+					.ok_or_else(|| LoadError::Parse(format!("<{}> without {}", tag.name, n)))
+			};
+
+			match (tag.name.as_str(), tag.closing) {
+				("scxml", false) => {
+					desc.name = tag.attrs.get("name").cloned().unwrap_or_default();
+					desc.start = tag.attrs.get("initial").cloned().unwrap_or_default();
+				}
+				("state", false) | ("final", false) => {
+					if state.is_some() {
+						return Err(LoadError::Parse(String::from("nested states are not supported")));
+					}
+					let id = attr("id")?;
+					if desc.start.is_empty() {
+						desc.start = id.clone();
+					}
+					desc.states.push(id.clone());
+					if !tag.empty {
+						state = Some(id);
+					}
+				}
+				("state", true) | ("final", true) => state = None,
+				("history", false) => {
+					if state.is_some() {
+						return Err(LoadError::Parse(String::from("nested states are not supported")));
+					}
+					let id = attr("id")?;
+					let kind = match tag.attrs.get("type").map(|t| t.as_str()) {
+						None | Some("shallow") => HistoryKind::Shallow,
+						Some("deep") => HistoryKind::Deep,
+						Some(t) => return Err(LoadError::Parse(format!("history {} of type {}", id, t))),
+					};
+					desc.states.push(id.clone());
+					history = Some(HistoryDescription {
+						history: id,
+						kind,
+						members: attr("fsm:members")?.split_whitespace().map(String::from).collect(),
+						initial: String::new(),
+					});
+					if !tag.empty {
+						continue;
+					}
+				}
+				("transition", false) if history.is_some() => {
+					if let Some(ref mut h) = history {
+						h.initial = attr("target")?;
+					}
+					continue;
+				}
+				("transition", false) | ("onentry", false) | ("onexit", false) => {
+					let from = state.clone()
+						.ok_or_else(|| LoadError::Parse(format!("<{}> outside of state", tag.name)))?;
+					named = Some(match tag.name.as_str() {
+						"transition" if !tag.attrs.contains_key("event") && tag.empty => {
+							desc.choices.push(ChoiceDescription {
+								choice: from,
+								to: attr("target")?,
+								guard: tag.attrs.get("cond").cloned(),
+							});
+							continue;
+						}
+						"transition" if !tag.attrs.contains_key("event") => {
+							Named::Completion(CompletionDescription {
+								state: from,
+								to: attr("target")?,
+								name: String::new(),
+								guard: tag.attrs.get("cond").cloned(),
+							})
+						}
+						"transition" => {
+							let event = attr("event")?;
+							if !desc.events.contains(&event) {
+								desc.events.push(event.clone());
+							}
+							// targetless transitions remain in their state, targeting it leaves it
+							let target = tag.attrs.get("target").cloned();
+							let kind = if target.as_ref() == Some(&from) {
+								TransitionKind::External
+							} else {
+								TransitionKind::Internal
+							};
+							Named::Transition(TransitionDescription {
+								kind,
+								to: target.unwrap_or_else(|| from.clone()),
+								from,
+								event,
+								name: String::new(),
+							})
+						}
+						"onentry" => Named::EntryExit(EntryExit::EntryTransition),
+						_ => Named::EntryExit(EntryExit::ExitTransition),
+					});
+					label = None;
+					if !tag.empty {
+						continue;
+					}
+				}
+				("log", false) => {
+					label = Some(attr("label")?);
+					continue;
+				}
+				_ => {}
+			}
+
+			if tag.name == "history" {
+				match history.take() {
+					Some(ref h) if h.initial.is_empty() => {
+						return Err(LoadError::Parse(format!("history {} without transition", h.history)));
+					}
+					Some(h) => desc.histories.push(h),
+					None => {}
+				}
+				continue;
+			}
+
+			// element naming a transition is complete
+			if let Some(n) = named.take() {
+				let name = label.take().unwrap_or_default();
+				match n {
+					Named::Transition(mut t) => {
+						t.name = name;
+						desc.transitions.push(t);
+					}
+					Named::EntryExit(kind) => {
+						desc.entryexit.push(EntryExitDescription {
+							state: state.clone().unwrap_or_default(),
+							kind,
+							name,
+						});
+					}
+					Named::Completion(mut c) => {
+						c.name = name;
+						desc.completions.push(c);
+					}
+				}
+			}
+		}
+
+		if desc.start.is_empty() {
+			return Err(LoadError::Parse(String::from("no states")));
+		}
+
+		Ok(desc)
+	}
+}
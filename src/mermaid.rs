@@ -0,0 +1,98 @@
+//! Export of the machine structure as Mermaid `stateDiagram-v2`
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+
+use super::{FSM, EntryExit};
+use export::{export_id, render_string};
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	/// writes the machine as Mermaid `stateDiagram-v2` which can be embedded into
+	/// Markdown. Transitions are labelled with event and transition name, entry/exit
+	/// transitions are attached to their states as notes. Choices are `<<choice>>`
+	/// states with their branches labelled by guard. Mermaid has no histories, they
+	/// are states marked `[H]` or `[H*]` leading to their initial state with their
+	/// group in the note. Completion transitions are labelled with guard and name.
+	pub fn render_mermaid<W: io::Write>(&self, w: &mut W,
+	                                    state2name: &HashMap<StateType, &'static str>,
+	                                    event2name: &HashMap<EventType, &'static str>,
+	) -> Result<(), io::Error> {
+		let m = self.export(state2name, event2name);
+
+		writeln!(w, "---")?;
+		writeln!(w, "title: {}", m.name)?;
+		writeln!(w, "---")?;
+		writeln!(w, "stateDiagram-v2")?;
+
+		for s in m.states.iter() {
+			let id = export_id(s);
+			if !m.branches_of(s).is_empty() {
+				writeln!(w, "    state {} <<choice>>", id)?;
+			} else if let Some(h) = m.history_of(s) {
+				writeln!(w, "    state \"{} {}\" as {}", s, h.marker(), id)?;
+			} else if id != *s {
+				writeln!(w, "    state \"{}\" as {}", s, id)?;
+			}
+		}
+
+		writeln!(w, "    [*] --> {}", export_id(m.start))?;
+		for t in m.transitions.iter() {
+			write!(w, "    {} --> {} : {}", export_id(t.from), export_id(t.to), t.event)?;
+			match t.name {
+				Some(n) => writeln!(w, " / {}", n)?,
+				None => writeln!(w)?,
+			}
+		}
+		for b in m.branches.iter() {
+			writeln!(w, "    {} --> {} : {}", export_id(b.choice), export_id(b.to), b.label())?;
+		}
+		for c in m.completions.iter() {
+			write!(w, "    {} --> {}", export_id(c.from), export_id(c.to))?;
+			match c.label().as_str() {
+				"" => writeln!(w)?,
+				label => writeln!(w, " : {}", label)?,
+			}
+		}
+		for h in m.histories.iter() {
+			writeln!(w, "    {} --> {}", export_id(h.history), export_id(h.initial))?;
+		}
+
+		for s in m.states.iter() {
+			let entryexit = m.entryexit_of(s);
+			let history = m.history_of(s);
+			if entryexit.is_empty() && history.is_none() {
+				continue;
+			}
+			writeln!(w, "    note right of {}", export_id(s))?;
+			if let Some(h) = history {
+				writeln!(w, "        resumes {}", h.members.join(", "))?;
+			}
+			for ee in entryexit {
+				let kind = match ee.kind {
+					EntryExit::EntryTransition => "entry",
+					EntryExit::ExitTransition => "exit",
+				};
+				match ee.name {
+					Some(n) => writeln!(w, "        {} / {}", kind, n)?,
+					None => writeln!(w, "        {}", kind)?,
+				}
+			}
+			writeln!(w, "    end note")?;
+		}
+
+		Ok(())
+	}
+
+	/// provides the machine as Mermaid state diagram, see `render_mermaid`
+	pub fn mermaid_string(&self,
+	                      state2name: &HashMap<StateType, &'static str>,
+	                      event2name: &HashMap<EventType, &'static str>,
+	) -> Result<String, io::Error> {
+		render_string(|out| self.render_mermaid(out, state2name, event2name))
+	}
+}
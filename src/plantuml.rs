@@ -0,0 +1,91 @@
+//! Export of the machine structure as PlantUML state diagram
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+
+use super::{FSM, EntryExit, HistoryKind};
+use export::{export_id, render_string};
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	/// writes the machine as PlantUML `@startuml` state diagram. The start state
+	/// is entered from `[*]`, entry/exit transitions become state descriptions
+	/// and transitions are labelled with event and transition name. Choices are
+	/// `<<choice>>` states with their branches labelled by guard, histories
+	/// `<<history>>` or `<<history*>>` states leading to their initial state with
+	/// their group in a note. Completion transitions are dotted and labelled with
+	/// guard and name.
+	pub fn render_plantuml<W: io::Write>(&self, w: &mut W,
+	                                     state2name: &HashMap<StateType, &'static str>,
+	                                     event2name: &HashMap<EventType, &'static str>,
+	) -> Result<(), io::Error> {
+		let m = self.export(state2name, event2name);
+
+		writeln!(w, "@startuml")?;
+		writeln!(w, "title {}", m.name)?;
+		writeln!(w)?;
+
+		for s in m.states.iter() {
+			let id = export_id(s);
+			let stereotype = match m.history_of(s) {
+				Some(h) if h.kind == HistoryKind::Deep => " <<history*>>",
+				Some(_) => " <<history>>",
+				None if !m.branches_of(s).is_empty() => " <<choice>>",
+				None => "",
+			};
+			if id != *s {
+				writeln!(w, "state \"{}\" as {}{}", s, id, stereotype)?;
+			} else {
+				writeln!(w, "state {}{}", id, stereotype)?;
+			}
+			for ee in m.entryexit_of(s) {
+				let kind = match ee.kind {
+					EntryExit::EntryTransition => "entry",
+					EntryExit::ExitTransition => "exit",
+				};
+				match ee.name {
+					Some(n) => writeln!(w, "{} : {} / {}", id, kind, n)?,
+					None => writeln!(w, "{} : {}", id, kind)?,
+				}
+			}
+		}
+		writeln!(w)?;
+
+		writeln!(w, "[*] --> {}", export_id(m.start))?;
+		for t in m.transitions.iter() {
+			write!(w, "{} --> {} : {}", export_id(t.from), export_id(t.to), t.event)?;
+			match t.name {
+				Some(n) => writeln!(w, " / {}", n)?,
+				None => writeln!(w)?,
+			}
+		}
+		for b in m.branches.iter() {
+			writeln!(w, "{} --> {} : {}", export_id(b.choice), export_id(b.to), b.label())?;
+		}
+		for c in m.completions.iter() {
+			write!(w, "{} -[dotted]-> {}", export_id(c.from), export_id(c.to))?;
+			match c.label().as_str() {
+				"" => writeln!(w)?,
+				label => writeln!(w, " : {}", label)?,
+			}
+		}
+		for h in m.histories.iter() {
+			writeln!(w, "{} --> {}", export_id(h.history), export_id(h.initial))?;
+			writeln!(w, "note right of {} : resumes {}", export_id(h.history), h.members.join(", "))?;
+		}
+
+		writeln!(w, "@enduml")
+	}
+
+	/// provides the machine as PlantUML state diagram, see `render_plantuml`
+	pub fn plantuml_string(&self,
+	                       state2name: &HashMap<StateType, &'static str>,
+	                       event2name: &HashMap<EventType, &'static str>,
+	) -> Result<String, io::Error> {
+		render_string(|out| self.render_plantuml(out, state2name, event2name))
+	}
+}
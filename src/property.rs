@@ -0,0 +1,138 @@
+//! Property based testing of an FSM with `proptest`, enabled by the `proptest`
+//! feature.
+//!
+//! Random event sequences are run through fresh machines, invariants are checked
+//! after every event once the event queue drained and failing sequences are
+//! shrunk to a minimal one by removing events and replacing them with events
+//! sampled earlier.
+//!
+//! ```ignore
+//! let mut check = SequenceCheck::new(Box::new(build_fsm));
+//! check.add_invariant("balanced", Box::new(|s, x| ...));
+//! check.run(&mut TestRunner::default(),
+//!           &event_sequences(sample_events(events), 0..32))?;
+//! ```
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::Range;
+
+use proptest::collection::{self, VecStrategy, SizeRange};
+use proptest::sample::{self, Select};
+use proptest::strategy::Strategy;
+use proptest::test_runner::{TestCaseError, TestError, TestRunner};
+
+use super::RunsFSM;
+use explore::{Factory, Invariant, Trace, MAX_QUEUE_ROUNDS};
+
+/// strategy picking one of the sample events with its argument, shrinking towards
+/// the first one
+pub fn sample_events<EventType, TransitionFnArguments>(samples: Vec<(EventType,
+                                                                    Option<TransitionFnArguments>)>)
+                                                       -> Select<(EventType,
+                                                                  Option<TransitionFnArguments>)>
+	where EventType: Clone + Debug + 'static,
+	      TransitionFnArguments: Clone + Debug + 'static
+{
+	sample::select(samples)
+}
+
+/// strategy generating sequences of events drawn from `event` with a length
+/// within `len`, shrinking by removing events
+pub fn event_sequences<S: Strategy>(event: S, len: Range<usize>) -> VecStrategy<S> {
+	collection::vec(event, SizeRange::from(len))
+}
+
+/// runs event sequences on fresh machines and checks invariants after each event
+pub struct SequenceCheck<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	factory: Box<Factory<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>>,
+	invariants: Vec<(String, Box<Invariant<ExtendedState, StateType>>)>,
+	fail_on_error: bool,
+}
+
+impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+SequenceCheck<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      TransitionFnArguments: Clone + Debug,
+	      ErrorType: Debug
+{
+	/// new check on machines built by `factory` which must be deterministic.
+	/// Sequences leading to an error fail the check.
+	pub fn new(factory: Box<Factory<'a,
+		           ExtendedState,
+		           StateType,
+		           EventType,
+		           TransitionFnArguments,
+		           ErrorType>>)
+	           -> SequenceCheck<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		SequenceCheck {
+			factory,
+			invariants: Vec::new(),
+			fail_on_error: true,
+		}
+	}
+
+	/// adds an invariant checked after every event
+	pub fn add_invariant(&mut self, name: &str, invariant: Box<Invariant<ExtendedState, StateType>>) {
+		self.invariants.push((String::from(name), invariant));
+	}
+
+	/// `fail` - FALSE ends sequences leading to an `Errors` successfully instead,
+	/// e.g. when events are generated that are not valid in every state
+	pub fn set_fail_on_error(&mut self, fail: bool) {
+		self.fail_on_error = fail;
+	}
+
+	/// runs `sequence` on a fresh machine
+	pub fn check(&self, sequence: &Trace<EventType, TransitionFnArguments>)
+	             -> Result<(), TestCaseError> {
+		let mut fsm = (self.factory)();
+
+		for (step, (event, arg)) in sequence.iter().enumerate() {
+			let mut processed = fsm.add_events(&mut vec![(event.clone(), arg.clone().map(Box::new))])
+				.map(|_| ());
+			let mut rounds = 0;
+			while processed.is_ok() && fsm.events_pending() {
+				if rounds == MAX_QUEUE_ROUNDS {
+					return Err(TestCaseError::fail(format!("event queue did not drain after \
+					                                        {:?} at step {}", event, step)));
+				}
+				processed = fsm.process_event_queue().map(|_| ());
+				rounds += 1;
+			}
+
+			if let Err(e) = processed {
+				return if self.fail_on_error {
+					Err(TestCaseError::fail(format!("{:?} at step {}", e, step)))
+				} else {
+					Ok(())
+				};
+			}
+
+			let state = fsm.current_state();
+			let extstate = fsm.extended_state();
+			for (name, inv) in self.invariants.iter() {
+				if let Err(m) = inv(&state, &extstate) {
+					return Err(TestCaseError::fail(format!("invariant `{}` violated in {:?} \
+					                                        at step {}: {}",
+					                                       name, state, step, m)));
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// checks the sequences `strategy` generates with `runner`. A failure carries
+	/// the shrunk sequence.
+	pub fn run<S>(&self, runner: &mut TestRunner, strategy: &S)
+	              -> Result<(), TestError<Trace<EventType, TransitionFnArguments>>>
+		where S: Strategy<Value = Trace<EventType, TransitionFnArguments>>
+	{
+		runner.run(strategy, |sequence| self.check(&sequence))
+	}
+}
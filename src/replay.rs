@@ -0,0 +1,242 @@
+//! Recording of externally added events and deterministic replay of such
+//! recordings against a freshly built FSM.
+//!
+//! A `Recorder` attached to a running machine captures every event (and its
+//! argument) handed to `add_events` together with the state the machine ends up
+//! in and the result of each `process_event_queue`. The resulting `Recording` can be
+//! serialized with any serde format when the `serde` feature is enabled and
+//! later fed into `replay` to verify that the same transition table walks
+//! through the same sequence of states with the same results. Checkpoints and
+//! restarts of supervised machines are recorded as well and replayed by
+//! restoring the replayed machine the same way.
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use super::{FSM, RunsFSM};
+
+/// trait implemented by anything that wants to observe the inputs of an FSM
+pub trait RecordsFSM<EventType, StateType, TransitionFnArguments> {
+	/// an event has been added from the outside of the machine
+	fn record_event(&mut self, event: &EventType, arg: Option<&TransitionFnArguments>);
+	/// the event queue has been processed with `result` and left the machine in
+	/// `state`
+	fn record_processed(&mut self, state: &StateType, result: Processed);
+	/// the machine in `state` has been checkpointed, see `Supervisor`
+	fn record_checkpoint(&mut self, _state: &StateType) {}
+	/// the machine has been replaced by a fresh one, restored to the last
+	/// checkpoint if `restored`, which is now in `state`, see `Supervisor`
+	fn record_restart(&mut self, _state: &StateType, _restored: bool) {}
+}
+
+/// result of `process_event_queue` in a recording, errors by their debug output
+/// so recordings do not depend on the error type
+pub type Processed = Result<u32, String>;
+
+/// single step of a recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RecordEntry<EventType, StateType, TransitionFnArguments> {
+	/// event added from the outside with its optional argument
+	Event(EventType, Option<TransitionFnArguments>),
+	/// `process_event_queue` ran with the given result and left the machine in
+	/// the given state
+	Processed(StateType, Processed),
+	/// the machine has been checkpointed in the given state
+	Checkpoint(StateType),
+	/// the machine has been replaced by a fresh one now in the given state
+	Restarted(StateType),
+	/// the machine has been replaced by a fresh one restored to the last
+	/// checkpoint, i.e. to the given state
+	Restored(StateType),
+}
+
+/// ordered log of the inputs to an FSM and the states it went through
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Recording<EventType, StateType, TransitionFnArguments> {
+	pub entries: Vec<RecordEntry<EventType, StateType, TransitionFnArguments>>,
+}
+
+impl<EventType, StateType, TransitionFnArguments> Default
+for Recording<EventType, StateType, TransitionFnArguments> {
+	fn default() -> Recording<EventType, StateType, TransitionFnArguments> {
+		Recording {
+			entries: Vec::new(),
+		}
+	}
+}
+
+impl<EventType, StateType, TransitionFnArguments>
+Recording<EventType, StateType, TransitionFnArguments> {
+	/// sequence of states the machine has been observed in after processing and
+	/// restarts
+	pub fn states(&self) -> Vec<&StateType> {
+		self.entries.iter().filter_map(|e| {
+			match *e {
+				RecordEntry::Processed(ref s, _) |
+				RecordEntry::Restarted(ref s) |
+				RecordEntry::Restored(ref s) => Some(s),
+				_ => None,
+			}
+		}).collect()
+	}
+}
+
+/// recorder that can be attached to an FSM via `FSM::set_recorder`. It is
+/// a cheap shared handle so a clone can be kept to get at the recording
+/// while the machine runs.
+pub struct Recorder<EventType, StateType, TransitionFnArguments> {
+	recording: Rc<RefCell<Recording<EventType, StateType, TransitionFnArguments>>>,
+}
+
+impl<EventType, StateType, TransitionFnArguments> Clone
+for Recorder<EventType, StateType, TransitionFnArguments> {
+	fn clone(&self) -> Recorder<EventType, StateType, TransitionFnArguments> {
+		Recorder {
+			recording: self.recording.clone(),
+		}
+	}
+}
+
+impl<EventType, StateType, TransitionFnArguments> Default
+for Recorder<EventType, StateType, TransitionFnArguments> {
+	fn default() -> Recorder<EventType, StateType, TransitionFnArguments> {
+		Recorder {
+			recording: Rc::new(RefCell::new(Recording::default())),
+		}
+	}
+}
+
+impl<EventType, StateType, TransitionFnArguments>
+Recorder<EventType, StateType, TransitionFnArguments>
+	where EventType: Clone,
+	      StateType: Clone,
+	      TransitionFnArguments: Clone
+{
+	pub fn new() -> Recorder<EventType, StateType, TransitionFnArguments> {
+		Recorder::default()
+	}
+
+	/// copy of everything recorded so far
+	pub fn recording(&self) -> Recording<EventType, StateType, TransitionFnArguments> {
+		self.recording.borrow().clone()
+	}
+
+	/// hands out the recording so far and starts with an empty one
+	pub fn take(&self) -> Recording<EventType, StateType, TransitionFnArguments> {
+		self.recording.replace(Recording::default())
+	}
+}
+
+impl<EventType, StateType, TransitionFnArguments>
+RecordsFSM<EventType, StateType, TransitionFnArguments>
+for Recorder<EventType, StateType, TransitionFnArguments>
+	where EventType: Clone,
+	      StateType: Clone,
+	      TransitionFnArguments: Clone
+{
+	fn record_event(&mut self, event: &EventType, arg: Option<&TransitionFnArguments>) {
+		self.recording.borrow_mut().entries.push(RecordEntry::Event(event.clone(),
+		                                                            arg.cloned()));
+	}
+
+	fn record_processed(&mut self, state: &StateType, result: Processed) {
+		self.recording.borrow_mut().entries.push(RecordEntry::Processed(state.clone(), result));
+	}
+
+	fn record_checkpoint(&mut self, state: &StateType) {
+		self.recording.borrow_mut().entries.push(RecordEntry::Checkpoint(state.clone()));
+	}
+
+	fn record_restart(&mut self, state: &StateType, restored: bool) {
+		let entry = if restored {
+			RecordEntry::Restored(state.clone())
+		} else {
+			RecordEntry::Restarted(state.clone())
+		};
+		self.recording.borrow_mut().entries.push(entry);
+	}
+}
+
+/// replay did not walk the machine through the recorded states or processing
+/// did not give the recorded result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayDivergence<StateType> {
+	/// index of the `RecordEntry::Processed`, `RecordEntry::Restarted` or
+	/// `RecordEntry::Restored` entry in the recording that diverged
+	pub step: usize,
+	/// state found in the recording
+	pub expected: StateType,
+	/// state the replayed machine is in
+	pub found: StateType,
+	/// result of the processing found in the recording, `Ok(0)` for restarts
+	pub expected_result: Processed,
+	/// result of the processing of the replayed machine
+	pub found_result: Processed,
+}
+
+/// feeds a recording into a (normally freshly built) FSM. The events are added
+/// and processed in the recorded order and after every processing the state of
+/// the machine and the result of `process_event_queue` are compared with the
+/// recorded ones. Restarts are replayed by restoring the machine to how it has
+/// been handed in or to the checkpoint taken at the last
+/// `RecordEntry::Checkpoint`, so the machine handed in must be fresh then.
+///
+/// `returns` - number of states verified or the first divergence found
+pub fn replay<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>(
+	fsm: &mut FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+	recording: &Recording<EventType, StateType, TransitionFnArguments>)
+	-> Result<u32, ReplayDivergence<StateType>>
+	where ExtendedState: Clone,
+	      StateType: Clone + PartialEq + Eq + Hash + Debug + Sized,
+	      EventType: Clone + PartialEq + Eq + Hash + Debug + Sized,
+	      TransitionFnArguments: Clone,
+	      ErrorType: Debug
+{
+	let mut verified = 0;
+	let fresh = fsm.checkpoint();
+	let mut checkpoint = None;
+
+	for (step, entry) in recording.entries.iter().enumerate() {
+		let (expected, expected_result, found_result) = match *entry {
+			RecordEntry::Event(ref event, ref arg) => {
+				let _ = fsm.add_events(&mut vec![(event.clone(),
+				                                  arg.clone().map(Box::new))]);
+				continue;
+			}
+			RecordEntry::Checkpoint(_) => {
+				checkpoint = Some(fsm.checkpoint());
+				continue;
+			}
+			RecordEntry::Processed(ref expected, ref expected_result) => {
+				let found_result = fsm.process_event_queue().map_err(|e| format!("{:?}", e));
+				(expected, expected_result.clone(), found_result)
+			}
+			RecordEntry::Restarted(ref expected) => {
+				fsm.restore(fresh.clone());
+				(expected, Ok(0), Ok(0))
+			}
+			RecordEntry::Restored(ref expected) => {
+				fsm.restore(checkpoint.clone().unwrap_or_else(|| fresh.clone()));
+				(expected, Ok(0), Ok(0))
+			}
+		};
+
+		let found = fsm.current_state();
+		if found != *expected || found_result != expected_result {
+			return Err(ReplayDivergence {
+				step,
+				expected: expected.clone(),
+				found,
+				expected_result,
+				found_result,
+			});
+		}
+		verified += 1;
+	}
+
+	Ok(verified)
+}
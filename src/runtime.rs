@@ -0,0 +1,479 @@
+//! Runtime owning many machines of the same type that exchange events.
+//!
+//! Machines are identified by an id. Transitions address events to other machines
+//! by returning them like any other event, the `Router` of the runtime tells which
+//! machine each of them is for, so machines built without knowing the runtime
+//! can send as well. Alternatively they post them through a `Postbox` captured
+//! when the machines were built. The runtime delivers addressed events after every
+//! processing as if they had been added from the outside. Processing is scheduled
+//! round robin, each machine with events pending processes its queue once per
+//! round so no machine can starve the others. Errors are reported per machine
+//! without cutting the round short.
+//!
+//! Transitions can also spawn child machines, e.g. a session per neighbor,
+//! through a `Spawner`. The parent is notified by an event when a child reached
+//! one of its final states or failed, the child is removed from the runtime then.
+//! Tearing down a machine tears down its children as well.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use slog::Logger;
+
+use super::{FSM, Errors, RunsFSM, OptionalFnArg};
+
+/// event addressed to a machine with its optional argument
+pub type Addressed<Id, EventType, TransitionFnArguments> =
+(Id, EventType, OptionalFnArg<TransitionFnArguments>);
+
+/// machine other than `from` an event returned by one of the transitions of
+/// `from` is addressed to, None keeps it in `from`
+pub type Router<Id, EventType, TransitionFnArguments> =
+dyn Fn(&Id, &EventType, Option<&TransitionFnArguments>) -> Option<Id>;
+
+/// cloneable handle to address events to machines of a runtime
+pub struct Postbox<Id, EventType, TransitionFnArguments> {
+	queue: Rc<RefCell<VecDeque<Addressed<Id, EventType, TransitionFnArguments>>>>,
+}
+
+impl<Id, EventType, TransitionFnArguments> Clone for Postbox<Id, EventType, TransitionFnArguments> {
+	fn clone(&self) -> Postbox<Id, EventType, TransitionFnArguments> {
+		Postbox {
+			queue: self.queue.clone(),
+		}
+	}
+}
+
+impl<Id, EventType, TransitionFnArguments> Postbox<Id, EventType, TransitionFnArguments> {
+	/// addresses `event` to the machine `to`, delivered when the current processing
+	/// is done
+	pub fn post(&self, to: Id, event: EventType, arg: OptionalFnArg<TransitionFnArguments>) {
+		self.queue.borrow_mut().push_back((to, event, arg));
+	}
+
+	/// `returns` - number of events not delivered yet
+	pub fn pending(&self) -> usize {
+		self.queue.borrow().len()
+	}
+
+	fn take(&self) -> Option<Addressed<Id, EventType, TransitionFnArguments>> {
+		self.queue.borrow_mut().pop_front()
+	}
+}
+
+/// how a child machine ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChildExit<Id, EventType, StateType, ErrorType> {
+	/// child reached the given final state with no events left to process
+	Finished(Id, StateType),
+	/// child failed to process its events
+	Failed(Id, Errors<EventType, StateType, ErrorType>),
+}
+
+/// turns the end of a child into the event notifying its parent
+pub type ChildNotification<Id, EventType, StateType, TransitionFnArguments, ErrorType> =
+dyn Fn(ChildExit<Id, EventType, StateType, ErrorType>)
+	-> (EventType, OptionalFnArg<TransitionFnArguments>);
+
+/// machine spawned by another one
+struct Child<Id, EventType, StateType, TransitionFnArguments, ErrorType> {
+	parent: Id,
+	final_states: Vec<StateType>,
+	notify: Box<ChildNotification<Id, EventType, StateType, TransitionFnArguments, ErrorType>>,
+}
+
+/// change of the machines the runtime owns requested by a transition
+enum Request<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	Spawn(Id,
+	      Box<FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>>,
+	      Child<Id, EventType, StateType, TransitionFnArguments, ErrorType>),
+	Teardown(Id),
+}
+
+type RequestQueue<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> =
+Rc<RefCell<VecDeque<Request<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>>>>;
+
+/// cloneable handle to spawn and tear down machines of a runtime
+pub struct Spawner<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	requests: RequestQueue<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+}
+
+impl<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> Clone
+for Spawner<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	fn clone(&self) -> Spawner<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		Spawner {
+			requests: self.requests.clone(),
+		}
+	}
+}
+
+impl<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+Spawner<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	/// requests `fsm` to be added under `id` as child of `parent` when the current
+	/// processing is done, before events addressed meanwhile are delivered
+	///   * `final_states` - states the child is done in once it has no events left
+	///   * `notify`       - builds the event the parent gets when the child ended
+	pub fn spawn(&self, parent: Id, id: Id,
+	             fsm: FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+	             final_states: Vec<StateType>,
+	             notify: Box<ChildNotification<Id,
+		             EventType,
+		             StateType,
+		             TransitionFnArguments,
+		             ErrorType>>) {
+		self.requests.borrow_mut().push_back(Request::Spawn(id, Box::new(fsm), Child {
+			parent,
+			final_states,
+			notify,
+		}));
+	}
+
+	/// requests the machine `id` and all its children to be removed without
+	/// notifying its parent
+	pub fn teardown(&self, id: Id) {
+		self.requests.borrow_mut().push_back(Request::Teardown(id));
+	}
+}
+
+/// errors the runtime runs into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError<Id, EventType, StateType, ErrorType> {
+	/// event has been addressed to a machine the runtime does not own
+	UnknownMachine(Id, EventType),
+	/// machine failed to process its events and has been removed from the runtime
+	Failed(Id, Errors<EventType, StateType, ErrorType>),
+	/// events are still outstanding after the given number of rounds
+	Busy(usize),
+	/// machine to be spawned under an id already taken
+	Duplicate(Id),
+}
+
+/// errors of a round or a run, which went on with the other machines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunErrors<Id, EventType, StateType, ErrorType> {
+	/// number of events processed nevertheless
+	pub processed: u32,
+	/// errors in the order they occurred
+	pub errors: Vec<RuntimeError<Id, EventType, StateType, ErrorType>>,
+}
+
+/// result of a round or a run
+pub type RunResult<Id, EventType, StateType, ErrorType> =
+Result<u32, RunErrors<Id, EventType, StateType, ErrorType>>;
+
+/// runtime owning machines identified by `Id`
+pub struct Runtime<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where Id: Clone + Eq + Hash,
+	      StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	machines: HashMap<Id, FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>>,
+	/// ids in the order the machines have been added, i.e. the round robin order
+	order: Vec<Id>,
+	postbox: Postbox<Id, EventType, TransitionFnArguments>,
+	requests: RequestQueue<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+	children: HashMap<Id, Child<Id, EventType, StateType, TransitionFnArguments, ErrorType>>,
+	router: Option<Box<Router<Id, EventType, TransitionFnArguments>>>,
+	log: Logger,
+}
+
+impl<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+Runtime<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where Id: Clone + Eq + Hash + Debug,
+	      StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      ErrorType: Debug
+{
+	/// new runtime without machines
+	pub fn new(log: Logger)
+	           -> Runtime<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		Runtime {
+			machines: HashMap::new(),
+			order: Vec::new(),
+			postbox: Postbox {
+				queue: Rc::new(RefCell::new(VecDeque::new())),
+			},
+			requests: Rc::new(RefCell::new(VecDeque::new())),
+			children: HashMap::new(),
+			router: None,
+			log,
+		}
+	}
+
+	/// sets the router addressing events returned by transitions to other machines,
+	/// without all of them stay in the machine they have been returned in
+	///
+	/// `returns` - previously set router if any
+	pub fn set_router(&mut self, router: Option<Box<Router<Id, EventType, TransitionFnArguments>>>)
+	                  -> Option<Box<Router<Id, EventType, TransitionFnArguments>>> {
+		std::mem::replace(&mut self.router, router)
+	}
+
+	/// `returns` - postbox transitions can address events to machines with
+	pub fn postbox(&self) -> Postbox<Id, EventType, TransitionFnArguments> {
+		self.postbox.clone()
+	}
+
+	/// `returns` - spawner transitions can add child machines with
+	pub fn spawner(&self)
+	               -> Spawner<Id, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		Spawner {
+			requests: self.requests.clone(),
+		}
+	}
+
+	/// adds a machine under `id`
+	///
+	/// `returns` - the machine previously added under `id` if any
+	pub fn add_machine(&mut self, id: Id,
+	                   fsm: FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>)
+	                   -> Option<FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>> {
+		debug!(self.log, "runtime adding machine {:?}", id);
+		let previous = self.machines.insert(id.clone(), fsm);
+		if previous.is_none() {
+			self.order.push(id);
+		}
+		previous
+	}
+
+	/// removes the machine `id` and all its children, events still addressed to
+	/// them will fail delivery
+	pub fn remove_machine(&mut self, id: &Id)
+	                      -> Option<FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>> {
+		let children = self.children.iter()
+			.filter(|&(_, c)| c.parent == *id)
+			.map(|(i, _)| i.clone())
+			.collect::<Vec<_>>();
+		for c in children.iter() {
+			self.remove_machine(c);
+		}
+
+		self.children.remove(id);
+		self.order.retain(|i| i != id);
+		self.machines.remove(id)
+	}
+
+	/// id of the machine that spawned `id` if it has been spawned
+	pub fn parent(&self, id: &Id) -> Option<&Id> {
+		self.children.get(id).map(|c| &c.parent)
+	}
+
+	pub fn machine(&self, id: &Id)
+	               -> Option<&FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>> {
+		self.machines.get(id)
+	}
+
+	pub fn machine_mut(&mut self, id: &Id)
+	                   -> Option<&mut FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>> {
+		self.machines.get_mut(id)
+	}
+
+	/// ids of all machines in round robin order
+	pub fn ids(&self) -> &[Id] {
+		&self.order
+	}
+
+	/// addresses an event to a machine from the outside
+	pub fn post(&self, to: Id, event: EventType, arg: OptionalFnArg<TransitionFnArguments>) {
+		self.postbox.post(to, event, arg)
+	}
+
+	/// `returns` - TRUE if neither the postbox nor any machine has events outstanding
+	/// and no machines are to be spawned or torn down
+	pub fn is_idle(&self) -> bool {
+		self.postbox.pending() == 0 && self.requests.borrow().is_empty() &&
+			self.machines.values().all(|m| !m.events_pending())
+	}
+
+	/// removes a child that ended and notifies its parent
+	fn child_exited(&mut self, id: &Id, exit: ChildExit<Id, EventType, StateType, ErrorType>) {
+		debug!(self.log, "runtime child {:?} exited with {:?}", id, exit);
+		if let Some(child) = self.children.remove(id) {
+			let (event, arg) = (child.notify)(exit);
+			self.postbox.post(child.parent, event, arg);
+		}
+		self.remove_machine(id);
+	}
+
+	/// moves the events queued in `id` the router addresses to other machines to
+	/// the postbox
+	fn route(&mut self, id: &Id) {
+		let (router, m) = match (self.router.as_ref(), self.machines.get_mut(id)) {
+			(Some(router), Some(m)) => (router, m),
+			_ => return,
+		};
+		for (event, arg) in m.event_queue.drain(..).collect::<Vec<_>>() {
+			match router(id, &event, arg.as_deref()) {
+				Some(ref to) if to != id => self.postbox.post(to.clone(), event, arg),
+				_ => m.event_queue.push_back((event, arg)),
+			}
+		}
+	}
+
+	/// removes the children in one of their final states with no events left and
+	/// notifies their parents
+	fn finish_children(&mut self) {
+		let finished = self.children.iter()
+			.filter_map(|(id, c)| {
+				let m = self.machines.get(id)?;
+				let state = m.current_state();
+				if !m.events_pending() && c.final_states.contains(&state) {
+					Some((id.clone(), state))
+				} else {
+					None
+				}
+			})
+			.collect::<Vec<_>>();
+		for (id, state) in finished {
+			self.child_exited(&id, ChildExit::Finished(id.clone(), state));
+		}
+	}
+
+	/// executes spawn and teardown requests, then delivers everything in the postbox
+	/// to the addressed machines, until children that finished meanwhile have
+	/// notified their parents. Spawns under ids taken and events that cannot be
+	/// delivered are dropped.
+	fn deliver(&mut self, errors: &mut Vec<RuntimeError<Id, EventType, StateType, ErrorType>>) {
+		loop {
+			self.execute(errors);
+			self.empty_postbox(errors);
+			self.finish_children();
+			if self.postbox.pending() == 0 && self.requests.borrow().is_empty() {
+				break;
+			}
+		}
+	}
+
+	/// executes spawn and teardown requests
+	fn execute(&mut self, errors: &mut Vec<RuntimeError<Id, EventType, StateType, ErrorType>>) {
+		loop {
+			let request = self.requests.borrow_mut().pop_front();
+			match request {
+				Some(Request::Spawn(id, fsm, child)) => {
+					if self.machines.contains_key(&id) {
+						warn!(self.log, "runtime dropping spawn of {:?}, id taken", id);
+						errors.push(RuntimeError::Duplicate(id));
+						continue;
+					}
+					debug!(self.log, "runtime spawning {:?} for {:?}", id, child.parent);
+					self.children.insert(id.clone(), child);
+					self.add_machine(id, *fsm);
+				}
+				Some(Request::Teardown(id)) => {
+					debug!(self.log, "runtime tearing down {:?}", id);
+					self.remove_machine(&id);
+				}
+				None => break,
+			}
+		}
+	}
+
+	/// delivers everything in the postbox to the addressed machines
+	fn empty_postbox(&mut self, errors: &mut Vec<RuntimeError<Id, EventType, StateType, ErrorType>>) {
+		while let Some((to, event, arg)) = self.postbox.take() {
+			match self.machines.get_mut(&to) {
+				Some(m) => {
+					if let Err(e) = m.add_events(&mut vec![(event, arg)]) {
+						errors.push(RuntimeError::Failed(to, e));
+					}
+				}
+				None => {
+					warn!(self.log, "runtime dropping {:?} to unknown machine {:?}", event, to);
+					errors.push(RuntimeError::UnknownMachine(to, event));
+				}
+			}
+		}
+	}
+
+	/// one round, every machine with events pending processes its queue once and
+	/// the events addressed meanwhile are delivered right after
+	///
+	/// `returns` - number of events processed or the errors of the round together
+	///             with that number, a failing machine is removed from the runtime.
+	///             Failing children are removed too but only notify their parent.
+	pub fn run_round(&mut self) -> RunResult<Id, EventType, StateType, ErrorType> {
+		let mut processed = 0;
+		let mut errors = Vec::new();
+		self.deliver(&mut errors);
+
+		for id in self.order.clone() {
+			let res = match self.machines.get_mut(&id) {
+				Some(m) if m.events_pending() => m.process_event_queue(),
+				_ => continue,
+			};
+			match res {
+				Ok(n) => {
+					processed += n;
+					self.route(&id);
+				}
+				Err(e) => {
+					if self.children.contains_key(&id) {
+						self.child_exited(&id, ChildExit::Failed(id.clone(), e));
+					} else {
+						warn!(self.log, "runtime removing failed machine {:?}: {:?}", id, e);
+						self.remove_machine(&id);
+						errors.push(RuntimeError::Failed(id.clone(), e));
+					}
+				}
+			}
+
+
+			self.deliver(&mut errors);
+		}
+
+		if errors.is_empty() {
+			Ok(processed)
+		} else {
+			Err(RunErrors {
+				processed,
+				errors,
+			})
+		}
+	}
+
+	/// runs rounds until no events are outstanding anymore
+	///
+	///   * `max_rounds` - bound on the rounds run, machines that keep sending each
+	///     other events fail with `RuntimeError::Busy` after that many
+	///
+	/// `returns` - number of events processed or the errors of the first round
+	///             running into any together with the events processed up to then
+	pub fn run(&mut self, max_rounds: usize) -> RunResult<Id, EventType, StateType, ErrorType> {
+		let mut processed = 0;
+		for _ in 0..max_rounds {
+			if self.is_idle() {
+				return Ok(processed);
+			}
+			match self.run_round() {
+				Ok(n) => processed += n,
+				Err(mut e) => {
+					e.processed += processed;
+					return Err(e);
+				}
+			}
+		}
+		if self.is_idle() {
+			Ok(processed)
+		} else {
+			Err(RunErrors {
+				processed,
+				errors: vec![RuntimeError::Busy(max_rounds)],
+			})
+		}
+	}
+}
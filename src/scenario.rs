@@ -0,0 +1,227 @@
+//! Scripted scenarios driving an FSM step by step.
+//!
+//! A scenario is a list of steps, each posting an event or processing the events
+//! the machine queued itself, with expectations on the state the machine is in,
+//! the events it queued and its extended state after the step. Running it stops
+//! at the first expectation not met and reports what was expected and found.
+//!
+//! ```ignore
+//! let mut s = Scenario::new("good coin");
+//! s.post(Events::GotCoin, Some(Args::Coin(Good)))
+//!  .expect_state(States::CheckingMoney)
+//!  .expect_queued(vec![Events::AcceptMoney])
+//!  .process()
+//!  .expect("counted", Box::new(|x| x.coincounter == 1));
+//! s.assert_passes(&mut build_fsm());
+//! ```
+
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::{FSM, RunsFSM};
+
+/// predicate over the extended state
+pub type Predicate<ExtendedState> = dyn Fn(&ExtendedState) -> bool;
+
+/// what a step does to the machine
+enum Action<EventType, TransitionFnArguments> {
+	/// nothing, the expectations are checked on the machine as given
+	Start,
+	/// the event is added and the event queue processed once
+	Post(EventType, Option<TransitionFnArguments>),
+	/// the event queue is processed once
+	Process,
+}
+
+struct Step<ExtendedState, StateType, EventType, TransitionFnArguments> {
+	action: Action<EventType, TransitionFnArguments>,
+	state: Option<StateType>,
+	queued: Option<Vec<EventType>>,
+	error: bool,
+	predicates: Vec<(String, Box<Predicate<ExtendedState>>)>,
+}
+
+/// first expectation of a scenario not met
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioDivergence {
+	pub scenario: String,
+	/// index of the step, 0 being the machine before any step
+	pub step: usize,
+	/// what the step did, e.g. `post GotCoin`
+	pub action: String,
+	/// which expectation has not been met
+	pub expectation: &'static str,
+	pub expected: String,
+	pub found: String,
+}
+
+impl fmt::Display for ScenarioDivergence {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "scenario `{}` diverged at step {} ({}), {}:",
+		         self.scenario, self.step, self.action, self.expectation)?;
+		writeln!(f, "  expected: {}", self.expected)?;
+		write!(f, "  found:    {}", self.found)
+	}
+}
+
+/// scripted sequence of steps with expectations
+pub struct Scenario<ExtendedState, StateType, EventType, TransitionFnArguments> {
+	name: String,
+	steps: Vec<Step<ExtendedState, StateType, EventType, TransitionFnArguments>>,
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments>
+Scenario<ExtendedState, StateType, EventType, TransitionFnArguments>
+	where ExtendedState: Debug,
+	      StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      TransitionFnArguments: Clone
+{
+	/// new scenario, expectations given before the first step are checked on the
+	/// machine before it runs
+	pub fn new(name: &str) -> Scenario<ExtendedState, StateType, EventType, TransitionFnArguments> {
+		let mut s = Scenario {
+			name: String::from(name),
+			steps: Vec::new(),
+		};
+		s.step(Action::Start);
+		s
+	}
+
+	fn step(&mut self, action: Action<EventType, TransitionFnArguments>) -> &mut Self {
+		self.steps.push(Step {
+			action,
+			state: None,
+			queued: None,
+			error: false,
+			predicates: Vec::new(),
+		});
+		self
+	}
+
+	fn last(&mut self) -> &mut Step<ExtendedState, StateType, EventType, TransitionFnArguments> {
+		let last = self.steps.len() - 1;
+		&mut self.steps[last]
+	}
+
+	/// step adding `event` and processing the event queue once
+	pub fn post(&mut self, event: EventType, arg: Option<TransitionFnArguments>) -> &mut Self {
+		self.step(Action::Post(event, arg))
+	}
+
+	/// step processing the events queued by the previous step
+	pub fn process(&mut self) -> &mut Self {
+		self.step(Action::Process)
+	}
+
+	/// the machine is in `state` after the last step
+	pub fn expect_state(&mut self, state: StateType) -> &mut Self {
+		self.last().state = Some(state);
+		self
+	}
+
+	/// exactly `events` are queued in this order after the last step
+	pub fn expect_queued(&mut self, events: Vec<EventType>) -> &mut Self {
+		self.last().queued = Some(events);
+		self
+	}
+
+	/// processing in the last step returns an error, without the machine is
+	/// expected to process without errors
+	pub fn expect_error(&mut self) -> &mut Self {
+		self.last().error = true;
+		self
+	}
+
+	/// `predicate` named `name` holds on the extended state after the last step,
+	/// if not the extended state is reported as found
+	pub fn expect(&mut self, name: &str, predicate: Box<Predicate<ExtendedState>>) -> &mut Self {
+		self.last().predicates.push((String::from(name), predicate));
+		self
+	}
+
+	/// runs the scenario on `fsm`
+	///
+	/// `returns` - number of steps run or the first divergence found
+	pub fn run<ErrorType: Debug>(&self,
+	                             fsm: &mut FSM<ExtendedState,
+		                             StateType,
+		                             EventType,
+		                             TransitionFnArguments,
+		                             ErrorType>)
+	                             -> Result<usize, ScenarioDivergence> {
+		for (i, step) in self.steps.iter().enumerate() {
+			let (action, processed) = match step.action {
+				Action::Start => (String::from("start"), Ok(0)),
+				Action::Post(ref event, ref arg) => {
+					let processed = fsm.add_events(&mut vec![(event.clone(),
+					                                          arg.clone().map(Box::new))])
+						.and_then(|_| fsm.process_event_queue());
+					(format!("post {:?}", event), processed)
+				}
+				Action::Process => (String::from("process"), fsm.process_event_queue()),
+			};
+
+			let diverged = |expectation: &'static str, expected: String, found: String| {
+				Err(ScenarioDivergence {
+					scenario: self.name.clone(),
+					step: i,
+					action: action.clone(),
+					expectation,
+					expected,
+					found,
+				})
+			};
+
+			match processed {
+				Err(ref e) if !step.error => {
+					return diverged("processing", String::from("no error"), format!("{:?}", e));
+				}
+				Ok(_) if step.error => {
+					return diverged("processing", String::from("an error"), String::from("no error"));
+				}
+				_ => {}
+			}
+
+			if let Some(ref state) = step.state {
+				let found = fsm.current_state();
+				if found != *state {
+					return diverged("state", format!("{:?}", state), format!("{:?}", found));
+				}
+			}
+
+			if let Some(ref queued) = step.queued {
+				let found = fsm.pending_events();
+				if found != *queued {
+					return diverged("queued events", format!("{:?}", queued), format!("{:?}", found));
+				}
+			}
+
+			let extstate = fsm.extended_state();
+			for (name, predicate) in step.predicates.iter() {
+				if !predicate(&extstate) {
+					return diverged("extended state", format!("`{}` holds", name),
+					                format!("{:?}", **extstate));
+				}
+			}
+		}
+
+		Ok(self.steps.len() - 1)
+	}
+
+	/// runs the scenario on `fsm`
+	///
+	/// # Panics
+	/// with the divergence if an expectation is not met
+	pub fn assert_passes<ErrorType: Debug>(&self,
+	                                       fsm: &mut FSM<ExtendedState,
+		                                       StateType,
+		                                       EventType,
+		                                       TransitionFnArguments,
+		                                       ErrorType>) {
+		if let Err(d) = self.run(fsm) {
+			panic!("{}", d);
+		}
+	}
+}
@@ -0,0 +1,126 @@
+//! Export of the machine structure as W3C SCXML document
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+
+use super::{FSM, EntryExit, HistoryKind};
+use export::{export_id, render_string};
+
+/// namespace of the attributes SCXML has no equivalent for
+pub const SCXML_FSM_NS: &str = "urn:extfsm";
+
+/// escapes a string for use in an XML attribute value
+pub fn xml_escape(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'&' => out.push_str("&amp;"),
+			'<' => out.push_str("&lt;"),
+			'>' => out.push_str("&gt;"),
+			'"' => out.push_str("&quot;"),
+			'\'' => out.push_str("&apos;"),
+			c => out.push(c),
+		}
+	}
+	out
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	/// writes the machine structure as SCXML document. Transition closures cannot
+	/// be exported so their names are carried as `<log label="name"/>` placeholders
+	/// in the transitions and `<onentry>`/`<onexit>` blocks, unnamed ones get an
+	/// empty label. Internal self transitions
+	/// do not run entry/exit transitions which SCXML expresses as targetless
+	/// transitions, external ones target their own state. Branches of choices are
+	/// eventless transitions in the order they are tried with the guard name as
+	/// `cond`, the branch taken if no guard holds comes last without. Completion
+	/// transitions are eventless transitions too, guarded by `cond` if at all,
+	/// with a `<log label>` even if they have no name to tell them from branches.
+	/// Histories are `<history>` elements transitioning to their initial state,
+	/// SCXML takes their group from the parent state so the flat document lists
+	/// it in an `fsm:members` attribute.
+	pub fn render_scxml<W: io::Write>(&self, w: &mut W,
+	                                  state2name: &HashMap<StateType, &'static str>,
+	                                  event2name: &HashMap<EventType, &'static str>,
+	) -> Result<(), io::Error> {
+		let m = self.export(state2name, event2name);
+
+		writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+		write!(w, "<scxml xmlns=\"http://www.w3.org/2005/07/scxml\" version=\"1.0\"")?;
+		if !m.histories.is_empty() {
+			write!(w, " xmlns:fsm=\"{}\"", SCXML_FSM_NS)?;
+		}
+		writeln!(w, " name=\"{}\" initial=\"{}\">", xml_escape(m.name), export_id(m.start))?;
+
+		for s in m.states.iter() {
+			if let Some(h) = m.history_of(s) {
+				let kind = match h.kind {
+					HistoryKind::Shallow => "shallow",
+					HistoryKind::Deep => "deep",
+				};
+				let members = h.members.iter().map(|m| export_id(m)).collect::<Vec<_>>();
+				writeln!(w, "  <history id=\"{}\" type=\"{}\" fsm:members=\"{}\">",
+				         export_id(s), kind, members.join(" "))?;
+				writeln!(w, "    <transition target=\"{}\"/>", export_id(h.initial))?;
+				writeln!(w, "  </history>")?;
+				continue;
+			}
+			writeln!(w, "  <state id=\"{}\">", export_id(s))?;
+
+			for ee in m.entryexit_of(s) {
+				let tag = match ee.kind {
+					EntryExit::EntryTransition => "onentry",
+					EntryExit::ExitTransition => "onexit",
+				};
+				writeln!(w, "    <{}>", tag)?;
+				writeln!(w, "      <log label=\"{}\"/>", xml_escape(ee.name.unwrap_or("")))?;
+				writeln!(w, "    </{}>", tag)?;
+			}
+
+			for t in m.transitions.iter().filter(|t| t.from == *s) {
+				write!(w, "    <transition event=\"{}\"", xml_escape(t.event))?;
+				if t.to != t.from || t.external {
+					write!(w, " target=\"{}\"", export_id(t.to))?;
+				}
+				writeln!(w, ">")?;
+				writeln!(w, "      <log label=\"{}\"/>", xml_escape(t.name.unwrap_or("")))?;
+				writeln!(w, "    </transition>")?;
+			}
+
+			if let Some(c) = m.completion_of(s) {
+				write!(w, "    <transition")?;
+				if let Some(g) = c.guard {
+					write!(w, " cond=\"{}\"", xml_escape(g))?;
+				}
+				writeln!(w, " target=\"{}\">", export_id(c.to))?;
+				writeln!(w, "      <log label=\"{}\"/>", xml_escape(c.name.unwrap_or("")))?;
+				writeln!(w, "    </transition>")?;
+			}
+
+			for b in m.branches_of(s) {
+				write!(w, "    <transition")?;
+				if !b.otherwise {
+					write!(w, " cond=\"{}\"", xml_escape(b.guard.unwrap_or("")))?;
+				}
+				writeln!(w, " target=\"{}\"/>", export_id(b.to))?;
+			}
+
+			writeln!(w, "  </state>")?;
+		}
+
+		writeln!(w, "</scxml>")
+	}
+
+	/// provides the machine structure as SCXML document, see `render_scxml`
+	pub fn scxml_string(&self,
+	                    state2name: &HashMap<StateType, &'static str>,
+	                    event2name: &HashMap<EventType, &'static str>,
+	) -> Result<String, io::Error> {
+		render_string(|out| self.render_scxml(out, state2name, event2name))
+	}
+}
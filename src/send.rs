@@ -0,0 +1,340 @@
+//! FSM that can be moved across threads.
+//!
+//! `FSM` stores its transitions as boxed closures without `Send` bound, so it
+//! cannot be handed to another thread even if all closures put in happen to be
+//! `Send`. `SendFSM` wraps an `FSM` and only accepts `Send` closures and
+//! recorders, which makes the whole machine `Send` as long as the extended state,
+//! states, events, arguments and errors are. Transitions, choice branches and
+//! completion transitions are built with the `Send` counterparts of their `FSM`
+//! builders. Machines can thus be sharded across worker threads, each of them
+//! still being run by one thread at a time.
+
+use std::cell::RefMut;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::Deref;
+
+use slog::Logger;
+
+use super::{FSM, RunsFSM, Errors, EntryExit, OptionalFnArg, TransitionResult,
+            DynamicTransitionResult, TransitionSource, TransitionTarget, TransitionKind,
+            EntryExitContext, ChoiceBranch, CompletionTransition, HistoryKind};
+use replay::RecordsFSM;
+
+/// `TransitionFn` that can be sent to another thread
+pub type SendTransitionFn<ExtendedState, EventType, StateType, TransitionFnArguments, ErrorType> =
+dyn Fn(RefMut<Box<ExtendedState>>,
+	EventType,
+	OptionalFnArg<TransitionFnArguments>)
+	-> TransitionResult<EventType, StateType, TransitionFnArguments, ErrorType> + Send;
+
+/// `DynamicTransitionFn` that can be sent to another thread
+pub type SendDynamicTransitionFn<ExtendedState, EventType, StateType, TransitionFnArguments, ErrorType> =
+dyn Fn(RefMut<Box<ExtendedState>>,
+	EventType,
+	OptionalFnArg<TransitionFnArguments>)
+	-> DynamicTransitionResult<EventType, StateType, TransitionFnArguments, ErrorType> + Send;
+
+/// `EntryExitTransitionFn` that can be sent to another thread
+pub type SendEntryExitTransitionFn<ExtendedState,
+                                   EventType,
+                                   StateType,
+                                   TransitionFnArguments,
+                                   ErrorType> = dyn Fn(RefMut<Box<ExtendedState>>)
+	-> TransitionResult<EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType> + Send;
+
+/// `ContextEntryExitTransitionFn` that can be sent to another thread
+pub type SendContextEntryExitTransitionFn<ExtendedState,
+                                          EventType,
+                                          StateType,
+                                          TransitionFnArguments,
+                                          ErrorType> =
+dyn Fn(RefMut<Box<ExtendedState>>, &EntryExitContext<StateType, EventType, TransitionFnArguments>)
+	-> TransitionResult<EventType,
+		StateType,
+		TransitionFnArguments,
+		ErrorType> + Send;
+
+/// `Guard` that can be sent to another thread
+pub type SendGuard<ExtendedState> = dyn Fn(&ExtendedState) -> bool + Send;
+
+/// recorder that can be sent to another thread
+pub type SendRecorder<EventType, StateType, TransitionFnArguments> =
+dyn RecordsFSM<EventType, StateType, TransitionFnArguments> + Send;
+
+/// *Final state machine that is `Send`*. Only adding closures differs from
+/// `FSM`, inspecting the machine goes through `Deref`
+pub struct SendFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	fsm: FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+}
+
+/// all closures and the recorder the wrapped machine holds have been checked to be
+/// `Send` when they were added and the remaining parts of the machine are `Send`
+/// given the type parameters are, see `assert_send`. The machine is only ever run
+/// through `&mut`.
+unsafe impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> Send
+for SendFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where ExtendedState: Send,
+	      StateType: Clone + Eq + Hash + Sized + Send,
+	      EventType: Clone + Eq + Hash + Sized + Send,
+	      TransitionFnArguments: Send,
+	      ErrorType: Send
+{}
+
+/// never called, fails to compile if a part of `FSM` is not `Send` although the
+/// type parameters are, or if a part has been added that is not checked here.
+/// Closures and the recorder are checked by `SendFSM` when they are added.
+#[allow(dead_code)]
+fn assert_send<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>(
+	fsm: FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>)
+	where ExtendedState: Send,
+	      StateType: Clone + Eq + Hash + Sized + Send,
+	      EventType: Clone + Eq + Hash + Sized + Send,
+	      TransitionFnArguments: Send,
+	      ErrorType: Send
+{
+	fn send<T: Send>(_: T) {}
+
+	let FSM {
+		name,
+		extended_state,
+		start_state,
+		current_state,
+		event_queue,
+		transitions: _,
+		statetransitions: _,
+		log,
+		recorder: _,
+		stats,
+		inbox,
+		choices: _,
+		histories,
+		completions: _,
+		keep_arg,
+	} = fsm;
+	send(name);
+	send(extended_state);
+	send(start_state);
+	send(current_state);
+	send(event_queue);
+	send(log);
+	send(stats);
+	send(inbox);
+	send(histories);
+	send(keep_arg);
+}
+
+/// `TransitionTarget` built from `Send` closures
+pub struct SendTransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>(
+	TransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>);
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+SendTransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone
+{
+	/// see `TransitionTarget::new`
+	pub fn new(endstate: StateType,
+	           transfn: Box<SendTransitionFn<ExtendedState,
+		           EventType,
+		           StateType,
+		           TransitionFnArguments,
+		           ErrorType>>,
+	           name: Option<&str>)
+	           -> SendTransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		SendTransitionTarget(TransitionTarget::new(endstate, transfn, name))
+	}
+
+	/// see `TransitionTarget::dynamic`
+	pub fn dynamic(first: StateType,
+	               rest: Vec<StateType>,
+	               transfn: Box<SendDynamicTransitionFn<ExtendedState,
+		               EventType,
+		               StateType,
+		               TransitionFnArguments,
+		               ErrorType>>,
+	               name: Option<&str>)
+	               -> SendTransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		SendTransitionTarget(TransitionTarget::dynamic(first, rest, transfn, name))
+	}
+
+	/// see `TransitionTarget::with_kind`
+	pub fn with_kind(self, kind: TransitionKind)
+	                 -> SendTransitionTarget<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		SendTransitionTarget(self.0.with_kind(kind))
+	}
+}
+
+/// `ChoiceBranch` with a `Send` guard
+pub struct SendChoiceBranch<ExtendedState, StateType>(ChoiceBranch<ExtendedState, StateType>);
+
+impl<ExtendedState, StateType> SendChoiceBranch<ExtendedState, StateType> {
+	/// see `ChoiceBranch::new`
+	pub fn new(target: StateType, guard: Box<SendGuard<ExtendedState>>, name: Option<&str>)
+	           -> SendChoiceBranch<ExtendedState, StateType> {
+		SendChoiceBranch(ChoiceBranch::new(target, guard, name))
+	}
+}
+
+/// `CompletionTransition` built from `Send` closures
+pub struct SendCompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>(
+	CompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>);
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+SendCompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+	/// see `CompletionTransition::new`
+	pub fn new(endstate: StateType,
+	           transfn: Box<SendEntryExitTransitionFn<ExtendedState,
+		           EventType,
+		           StateType,
+		           TransitionFnArguments,
+		           ErrorType>>,
+	           name: Option<&str>)
+	           -> SendCompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		SendCompletionTransition(CompletionTransition::new(endstate, transfn, name))
+	}
+
+	/// see `CompletionTransition::with_guard`
+	pub fn with_guard(self, guard: Box<SendGuard<ExtendedState>>, name: Option<&str>)
+	                  -> SendCompletionTransition<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		SendCompletionTransition(self.0.with_guard(guard, name))
+	}
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+SendFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	/// new FSM, see `FSM::new`
+	pub fn new(start_state: StateType,
+	           extended_init: Box<ExtendedState>,
+	           name: &str,
+	           log: Logger)
+	           -> SendFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		SendFSM {
+			fsm: FSM::new(start_state, extended_init, name, log),
+		}
+	}
+
+	/// new transition, see `FSM::add_transition`
+	///
+	/// `returns` - TRUE if transition has been inserted,
+	///             FALSE if a previous has been overwritten!
+	pub fn add_transition(&mut self,
+	                      from: TransitionSource<StateType, EventType>,
+	                      to: SendTransitionTarget<ExtendedState,
+		                      StateType,
+		                      EventType,
+		                      TransitionFnArguments,
+		                      ErrorType>) -> bool {
+		self.fsm.add_transition(from, to.0)
+	}
+
+	/// declares a choice pseudo-state, see `FSM::add_choice`
+	pub fn add_choice(&mut self, choice: StateType,
+	                  branches: Vec<SendChoiceBranch<ExtendedState, StateType>>,
+	                  otherwise: StateType) -> bool {
+		self.fsm.add_choice(choice, branches.into_iter().map(|b| b.0).collect(), otherwise)
+	}
+
+	/// declares a history pseudo-state, see `FSM::add_history`
+	pub fn add_history(&mut self, history: StateType, kind: HistoryKind,
+	                   members: Vec<StateType>, initial: StateType) -> bool {
+		self.fsm.add_history(history, kind, members, initial)
+	}
+
+	/// new completion transition, see `FSM::add_completion`
+	pub fn add_completion(&mut self, state: StateType,
+	                      completion: SendCompletionTransition<ExtendedState,
+		                      StateType,
+		                      EventType,
+		                      TransitionFnArguments,
+		                      ErrorType>) -> bool {
+		self.fsm.add_completion(state, completion.0)
+	}
+
+	/// new enter/exit transition per state, see `FSM::add_enter_transition`
+	pub fn add_enter_transition(&mut self, case: (StateType, EntryExit),
+	                            trans: Box<SendEntryExitTransitionFn<ExtendedState,
+		                            EventType,
+		                            StateType,
+		                            TransitionFnArguments,
+		                            ErrorType>>,
+	                            name: Option<&str>) -> bool {
+		self.fsm.add_enter_transition(case, trans, name)
+	}
+
+	/// new enter/exit transition per state getting the context it is triggered
+	/// by, see `FSM::add_enter_transition_with_context`
+	pub fn add_enter_transition_with_context(&mut self, case: (StateType, EntryExit),
+	                                         trans: Box<SendContextEntryExitTransitionFn<ExtendedState,
+		                                         EventType,
+		                                         StateType,
+		                                         TransitionFnArguments,
+		                                         ErrorType>>,
+	                                         name: Option<&str>) -> bool
+		where TransitionFnArguments: Clone
+	{
+		self.fsm.add_enter_transition_with_context(case, trans, name)
+	}
+
+	/// attaches a recorder, see `FSM::set_recorder`
+	///
+	/// `returns` - previously attached recorder if any, which has been `Send`
+	pub fn set_recorder(&mut self,
+	                    recorder: Option<Box<SendRecorder<EventType,
+		                    StateType,
+		                    TransitionFnArguments>>>)
+	                    -> Option<Box<dyn RecordsFSM<EventType,
+		                    StateType,
+		                    TransitionFnArguments>>> {
+		self.fsm.set_recorder(recorder.map(|r| r as Box<dyn RecordsFSM<_, _, _>>))
+	}
+
+	/// see `FSM::set_recent_transitions`
+	pub fn set_recent_transitions(&mut self, n: usize) {
+		self.fsm.set_recent_transitions(n)
+	}
+
+	/// gives up the ability to be sent to another thread in exchange for the
+	/// full `FSM`
+	pub fn into_inner(self) -> FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		self.fsm
+	}
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> Deref
+for SendFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	type Target = FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.fsm
+	}
+}
+
+impl<ExtendedState, EventType, StateType, TransitionFnArguments, ErrorType>
+RunsFSM<EventType, StateType, TransitionFnArguments, ErrorType>
+for SendFSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + PartialEq + Eq + Hash + Debug + Sized,
+	      EventType: Clone + PartialEq + Eq + Hash + Debug + Sized,
+	      ErrorType: Debug
+{
+	fn add_events(&mut self,
+	              events: &mut Vec<(EventType, OptionalFnArg<TransitionFnArguments>)>)
+	              -> Result<u32, Errors<EventType, StateType, ErrorType>> {
+		self.fsm.add_events(events)
+	}
+
+	fn process_event_queue(&mut self) -> Result<u32, Errors<EventType, StateType, ErrorType>> {
+		self.fsm.process_event_queue()
+	}
+}
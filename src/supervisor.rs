@@ -0,0 +1,271 @@
+//! Supervision of machines restarting them when they fail.
+//!
+//! A machine returning an error from `process_event_queue` has to be shut down.
+//! `Supervisor` owns a machine together with the factory it has been built by and
+//! replaces a failed machine by a fresh one, optionally restored to the last
+//! checkpoint taken after a successful processing. Restarts are limited in
+//! intensity, a machine failing too often within the configured period is given
+//! up and the failure escalated to the caller.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+use slog::Logger;
+
+use super::{FSM, Errors, RunsFSM, OptionalFnArg};
+use explore::Factory;
+use replay::RecordsFSM;
+
+/// state and extended state of a machine at some point
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint<ExtendedState, StateType>
+	where StateType: Eq + Hash
+{
+	pub state: StateType,
+	pub extended_state: ExtendedState,
+	/// state each history pseudo-state resumes, those with none are left out
+	pub histories: HashMap<StateType, StateType>,
+}
+
+impl<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where ExtendedState: Clone,
+	      StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	/// `returns` - current state and a copy of the extended state, queued events
+	///             are not part of the checkpoint
+	pub fn checkpoint(&self) -> Checkpoint<ExtendedState, StateType> {
+		Checkpoint {
+			state: self.current_state.clone(),
+			extended_state: (**self.extended_state.borrow()).clone(),
+			histories: self.histories.iter()
+				.filter_map(|(h, history)| Some((h.clone(), history.last.clone()?)))
+				.collect(),
+		}
+	}
+
+	/// puts the machine into the state, extended state and histories of
+	/// `checkpoint` without running any entry or exit transitions, queued events
+	/// are dropped
+	pub fn restore(&mut self, mut checkpoint: Checkpoint<ExtendedState, StateType>) {
+		self.event_queue.clear();
+		self.current_state = checkpoint.state;
+		**self.extended_state.borrow_mut() = checkpoint.extended_state;
+		for (h, history) in self.histories.iter_mut() {
+			history.last = checkpoint.histories.remove(h);
+		}
+	}
+}
+
+/// how often and how a supervised machine is restarted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartPolicy {
+	/// restarts allowed within `period` before the failure is escalated
+	pub max_restarts: usize,
+	pub period: Duration,
+	/// checkpoint after every successful processing leaving no events queued and
+	/// restore the last checkpoint into restarted machines, without they start
+	/// afresh
+	pub restore_checkpoint: bool,
+}
+
+impl Default for RestartPolicy {
+	fn default() -> RestartPolicy {
+		RestartPolicy {
+			max_restarts: 3,
+			period: Duration::from_secs(5),
+			restore_checkpoint: true,
+		}
+	}
+}
+
+/// failure of a supervised machine exceeding the restart intensity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Escalation<EventType, StateType, ErrorType> {
+	/// name of the machine given up
+	pub name: String,
+	/// restarts within the period of the policy before the failure
+	pub restarts: usize,
+	pub error: Errors<EventType, StateType, ErrorType>,
+}
+
+impl<EventType, StateType, ErrorType> fmt::Display for Escalation<EventType, StateType, ErrorType>
+	where EventType: Debug,
+	      StateType: Debug,
+	      ErrorType: Debug
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "machine `{}` failed after {} restarts: {:?}", self.name, self.restarts, self.error)
+	}
+}
+
+/// owner of a machine restarting it on failures. The machine may be replaced on
+/// any processing, so `Deref` only lends out whichever machine runs right now
+pub struct Supervisor<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized
+{
+	factory: Box<Factory<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>>,
+	fsm: FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>,
+	policy: RestartPolicy,
+	checkpoint: Option<Checkpoint<ExtendedState, StateType>>,
+	/// times of the restarts within the period of the policy, oldest first
+	recent: VecDeque<Instant>,
+	restarts: usize,
+	log: Logger,
+}
+
+impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+Supervisor<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where ExtendedState: Clone,
+	      StateType: Clone + Eq + Hash + Debug + Sized,
+	      EventType: Clone + Eq + Hash + Debug + Sized,
+	      ErrorType: Debug
+{
+	/// new supervisor running a machine built by `factory`, which is called again
+	/// on every restart
+	pub fn new(factory: Box<Factory<'a,
+		           ExtendedState,
+		           StateType,
+		           EventType,
+		           TransitionFnArguments,
+		           ErrorType>>,
+	           policy: RestartPolicy,
+	           log: Logger)
+	           -> Supervisor<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		let fsm = factory();
+		Supervisor {
+			factory,
+			fsm,
+			policy,
+			checkpoint: None,
+			recent: VecDeque::new(),
+			restarts: 0,
+			log,
+		}
+	}
+
+	/// checkpoints the machine now, restarted machines are restored to it if the
+	/// policy says so
+	pub fn checkpoint(&mut self) {
+		self.checkpoint = Some(self.fsm.checkpoint());
+		if let Some(ref mut recorder) = self.fsm.recorder {
+			recorder.record_checkpoint(&self.fsm.current_state);
+		}
+	}
+
+	/// `returns` - checkpoint restarted machines are restored to
+	pub fn last_checkpoint(&self) -> Option<&Checkpoint<ExtendedState, StateType>> {
+		self.checkpoint.as_ref()
+	}
+
+	/// `returns` - number of restarts since the supervisor has been built
+	pub fn restarts(&self) -> usize {
+		self.restarts
+	}
+
+	/// adds events to the supervised machine, see `RunsFSM::add_events`
+	pub fn add_events(&mut self,
+	                  events: &mut Vec<(EventType, OptionalFnArg<TransitionFnArguments>)>)
+	                  -> Result<u32, Errors<EventType, StateType, ErrorType>> {
+		self.fsm.add_events(events)
+	}
+
+	/// attaches a recorder to the supervised machine, see `FSM::set_recorder`.
+	/// The recorder is moved to restarted machines and sees the checkpoints and
+	/// restarts, so its recording replays against a fresh machine.
+	///
+	/// `returns` - previously attached recorder if any
+	pub fn set_recorder(&mut self,
+	                    recorder: Option<Box<dyn RecordsFSM<EventType,
+		                    StateType,
+		                    TransitionFnArguments>>>)
+	                    -> Option<Box<dyn RecordsFSM<EventType,
+		                    StateType,
+		                    TransitionFnArguments>>> {
+		self.fsm.set_recorder(recorder)
+	}
+
+	/// processes the event queue of the supervised machine. A failing machine is
+	/// replaced by a fresh one, the events queued when it failed are lost but
+	/// handles given out keep posting into the fresh machine and its recorder
+	/// keeps recording.
+	///
+	/// `returns` - number of events processed, 0 if the machine has been restarted,
+	///             or the escalation if the restart intensity has been exceeded.
+	///             The failed machine is kept then and the supervisor should be
+	///             dropped.
+	pub fn process_event_queue(&mut self) -> Result<u32, Escalation<EventType, StateType, ErrorType>> {
+		match self.fsm.process_event_queue() {
+			Ok(n) => {
+				// checkpoints do not contain queued events, so the machine is only
+				// checkpointed once they are all processed
+				if self.policy.restore_checkpoint && !self.fsm.events_pending() {
+					self.checkpoint();
+				}
+				Ok(n)
+			}
+			Err(e) => self.restart(e).map(|_| 0),
+		}
+	}
+
+	/// replaces the failed machine unless it failed too often
+	fn restart(&mut self, error: Errors<EventType, StateType, ErrorType>)
+	           -> Result<(), Escalation<EventType, StateType, ErrorType>> {
+		let now = Instant::now();
+		while self.recent.front().is_some_and(|t| now.duration_since(*t) > self.policy.period) {
+			self.recent.pop_front();
+		}
+
+		if self.recent.len() >= self.policy.max_restarts {
+			error!(self.log, "supervisor giving up {} on {:?}", self.fsm.name(), error);
+			return Err(Escalation {
+				name: self.fsm.name().clone(),
+				restarts: self.recent.len(),
+				error,
+			});
+		}
+
+		warn!(self.log, "supervisor restarting {} on {:?}", self.fsm.name(), error);
+		self.recent.push_back(now);
+		self.restarts += 1;
+
+		let mut fsm = (self.factory)();
+		fsm.inbox = self.fsm.inbox.clone();
+		let mut restored = false;
+		if self.policy.restore_checkpoint {
+			if let Some(ref checkpoint) = self.checkpoint {
+				fsm.restore(checkpoint.clone());
+				restored = true;
+			}
+		}
+		if let Some(mut recorder) = self.fsm.recorder.take() {
+			recorder.record_restart(&fsm.current_state, restored);
+			fsm.recorder = Some(recorder);
+		}
+		self.fsm = fsm;
+		Ok(())
+	}
+
+	/// gives up supervision in exchange for the machine
+	pub fn into_inner(self) -> FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> {
+		self.fsm
+	}
+}
+
+impl<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType> Deref
+for Supervisor<'a, ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>
+	where StateType: Clone + Eq + Hash + Sized,
+	      EventType: Clone + Eq + Hash + Sized,
+{
+	type Target = FSM<ExtendedState, StateType, EventType, TransitionFnArguments, ErrorType>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.fsm
+	}
+}
@@ -0,0 +1,108 @@
+// generated code compiled against the handlers it is bound to, so changes to the
+// signatures or paths `codegen` emits break the build
+
+extern crate extfsm;
+#[macro_use]
+extern crate slog;
+
+use std::cell::RefMut;
+use std::fs;
+
+use slog::{Discard, Logger};
+
+use extfsm::{OptionalFnArg, RunsFSM, TransitionResult, DynamicTransitionResult};
+use extfsm::codegen::{CodegenOptions, generate};
+use extfsm::load::MachineDescription;
+
+include!("codegen/turnstile.rs");
+
+#[derive(Debug, Default)]
+pub struct Counters {
+	coins: u32,
+	unlocks: u32,
+	fixable: bool,
+}
+
+mod handlers {
+	use super::*;
+
+	type Result = TransitionResult<Events, States, (), ()>;
+
+	pub fn insert_coin(mut x: RefMut<Box<Counters>>, _: Events, _: OptionalFnArg<()>) -> Result {
+		x.coins += 1;
+		Ok(None)
+	}
+
+	pub fn kick(x: RefMut<Box<Counters>>, _: Events, _: OptionalFnArg<()>)
+	            -> DynamicTransitionResult<Events, States, (), ()> {
+		if x.fixable {
+			Ok((States::Broken, None))
+		} else {
+			Ok((States::Locked, None))
+		}
+	}
+
+	pub fn pass_through(_: RefMut<Box<Counters>>, _: Events, _: OptionalFnArg<()>) -> Result {
+		Ok(None)
+	}
+
+	pub fn return_coin(_: RefMut<Box<Counters>>, _: Events, _: OptionalFnArg<()>) -> Result {
+		Ok(None)
+	}
+
+	pub fn fix(_: RefMut<Box<Counters>>, _: Events, _: OptionalFnArg<()>) -> Result {
+		Ok(None)
+	}
+
+	pub fn count_unlocks(mut x: RefMut<Box<Counters>>) -> Result {
+		x.unlocks += 1;
+		Ok(None)
+	}
+
+	pub fn repaired(_: RefMut<Box<Counters>>) -> Result {
+		Ok(None)
+	}
+
+	pub fn paid(x: &Counters) -> bool {
+		x.coins > 0
+	}
+
+	pub fn fixable(x: &Counters) -> bool {
+		x.fixable
+	}
+}
+
+fn desc() -> MachineDescription {
+	MachineDescription::from_scxml(&fs::read_to_string("tests/codegen/turnstile.scxml").unwrap()).unwrap()
+}
+
+#[test]
+fn generated_runs() {
+	let mut fsm = build_fsm(Box::new(Counters {
+		fixable: true,
+		..Counters::default()
+	}), Logger::root(Discard, o!()));
+
+	for (event, state) in [(Events::Coin, States::Unlocked),
+	                       (Events::Push, States::Locked),
+	                       (Events::Kick, States::Broken),
+	                       (Events::Repair, States::Locked)] {
+		fsm.add_events(&mut vec![(event, None)]).unwrap();
+		fsm.process_event_queue().unwrap();
+		assert_eq!(fsm.current_state(), state);
+	}
+	assert_eq!(fsm.extended_state().unlocks, 1);
+	assert_eq!(fsm.describe(&States::names(), &Events::names()).states.len(), 6);
+}
+
+#[test]
+fn generated_is_current() {
+	let mut out = Vec::new();
+	generate(&desc(), &CodegenOptions::new("Counters", "()", "()"), &mut out).unwrap();
+	let code = String::from_utf8(out).unwrap();
+	if std::env::var("EXTFSM_BLESS").is_ok() {
+		fs::write("tests/codegen/turnstile.rs", &code).unwrap();
+	}
+	assert_eq!(code, fs::read_to_string("tests/codegen/turnstile.rs").unwrap(),
+	           "regenerate with EXTFSM_BLESS=1");
+}
@@ -0,0 +1,69 @@
+// generated by extfsm::codegen from the description of `turnstile`, do not edit
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum States {
+	Broken,
+	Checking,
+	Locked,
+	Repairing,
+	Resume,
+	Unlocked,
+}
+
+impl States {
+	/// all variants with their names
+	pub fn names() -> ::std::collections::HashMap<States, &'static str> {
+		let mut names = ::std::collections::HashMap::new();
+		names.insert(States::Broken, "Broken");
+		names.insert(States::Checking, "Checking");
+		names.insert(States::Locked, "Locked");
+		names.insert(States::Repairing, "Repairing");
+		names.insert(States::Resume, "Resume");
+		names.insert(States::Unlocked, "Unlocked");
+		names
+	}
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum Events {
+	Coin,
+	Kick,
+	Push,
+	Repair,
+}
+
+impl Events {
+	/// all variants with their names
+	pub fn names() -> ::std::collections::HashMap<Events, &'static str> {
+		let mut names = ::std::collections::HashMap::new();
+		names.insert(Events::Coin, "Coin");
+		names.insert(Events::Kick, "Kick");
+		names.insert(Events::Push, "Push");
+		names.insert(Events::Repair, "Repair");
+		names
+	}
+}
+
+/// builds the `turnstile` machine with the transitions from `handlers`
+pub fn build_fsm(extended_init: Box<Counters>, log: ::slog::Logger) -> ::extfsm::FSM<Counters, States, Events, (), ()> {
+	let mut fsm: ::extfsm::FSM<Counters, States, Events, (), ()> = ::extfsm::FSM::new(States::Locked, extended_init, "turnstile", log);
+	fsm.add_transition(::extfsm::TransitionSource::new(States::Locked, Events::Coin),
+	                   ::extfsm::TransitionTarget::new(States::Checking, Box::new(handlers::insert_coin), Some("InsertCoin")));
+	fsm.add_transition(::extfsm::TransitionSource::new(States::Locked, Events::Kick),
+	                   ::extfsm::TransitionTarget::dynamic(States::Locked, vec![States::Broken], Box::new(handlers::kick), Some("Kick")));
+	fsm.add_transition(::extfsm::TransitionSource::new(States::Unlocked, Events::Push),
+	                   ::extfsm::TransitionTarget::new(States::Locked, Box::new(handlers::pass_through), Some("PassThrough")));
+	fsm.add_transition(::extfsm::TransitionSource::new(States::Unlocked, Events::Coin),
+	                   ::extfsm::TransitionTarget::new(States::Unlocked, Box::new(handlers::return_coin), Some("ReturnCoin")).with_kind(::extfsm::TransitionKind::External));
+	fsm.add_transition(::extfsm::TransitionSource::new(States::Broken, Events::Repair),
+	                   ::extfsm::TransitionTarget::new(States::Repairing, Box::new(handlers::fix), Some("Fix")));
+	fsm.add_enter_transition((States::Unlocked, ::extfsm::EntryExit::EntryTransition),
+	                         Box::new(handlers::count_unlocks), Some("CountUnlocks"));
+	fsm.add_choice(States::Checking,
+	               vec![::extfsm::ChoiceBranch::new(States::Unlocked, Box::new(handlers::paid), Some("Paid"))],
+	               States::Locked);
+	fsm.add_history(States::Resume, ::extfsm::HistoryKind::Shallow, vec![States::Locked, States::Unlocked], States::Locked);
+	fsm.add_completion(States::Repairing,
+	                   ::extfsm::CompletionTransition::new(States::Resume, Box::new(handlers::repaired), Some("Repaired")).with_guard(Box::new(handlers::fixable), Some("Fixable")));
+	fsm
+}